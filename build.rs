@@ -1,5 +1,6 @@
-use std::fs::File;
+use std::fs::{self, File};
 use std::path::Path;
+use std::time::UNIX_EPOCH;
 use std::{env, io};
 
 use flate2::Compression;
@@ -9,18 +10,36 @@ use winresource::WindowsResource;
 #[tokio::main]
 async fn main() -> io::Result<()> {
     // Download new game data and save it in a location to be included by the source.
+    // This is the fallback used when newer data can't be downloaded at runtime.
     let out_dir = env::var_os("OUT_DIR").unwrap();
     let cache_path = Path::new(&out_dir).join("game_data.json");
+    let out_path = Path::new(&out_dir).join("game_data.gz");
 
     let mut db = anime_game_data::AnimeGameData::new_with_cache(&cache_path);
     if db.needs_update().await.unwrap() {
         db.update().await.unwrap();
-        let out_path = Path::new(&out_dir).join("game_data.gz");
-        let f = File::create(out_path).unwrap();
+        let f = File::create(&out_path).unwrap();
         let writer = GzEncoder::new(f, Compression::best());
         db.save_to_writer(writer).unwrap();
     }
 
+    // When the embedded data was downloaded, so a copy downloaded at runtime
+    // before an app update isn't preferred over it.
+    let downloaded = fs::metadata(&out_path)?.modified()?;
+    let downloaded = downloaded.duration_since(UNIX_EPOCH).unwrap_or_default();
+    println!(
+        "cargo:rustc-env=EMBEDDED_GAME_DATA_TIME={}",
+        downloaded.as_secs()
+    );
+
+    // The key updates are verified with.  Builds without one refuse every
+    // update.
+    let update_key = fs::read_to_string("keys/update.pub").unwrap_or_default();
+    if update_key.trim().is_empty() {
+        println!("cargo:warning=keys/update.pub is missing, this build won't install updates");
+    }
+    fs::write(Path::new(&out_dir).join("update.pub"), update_key)?;
+
     // Add icon to windows binary.
     if env::var_os("CARGO_CFG_WINDOWS").is_some() {
         WindowsResource::new()
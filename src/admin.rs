@@ -50,6 +50,14 @@ pub fn ensure_admin() {
         .chain(Some(0))
         .collect::<Vec<_>>();
     let args = args_str.encode_utf16().chain(Some(0)).collect::<Vec<_>>();
+    // Elevated processes otherwise start in System32, which would break
+    // relative paths in the arguments.
+    let current_dir = env::current_dir().ok().map(|dir| {
+        dir.as_os_str()
+            .encode_wide()
+            .chain(Some(0))
+            .collect::<Vec<_>>()
+    });
 
     unsafe {
         let mut options = SHELLEXECUTEINFOW {
@@ -59,7 +67,9 @@ pub fn ensure_admin() {
             lpVerb: w!("runas"),
             lpFile: PCWSTR(exe_path.as_ptr()),
             lpParameters: PCWSTR(args.as_ptr()),
-            lpDirectory: PCWSTR::null(),
+            lpDirectory: current_dir
+                .as_ref()
+                .map_or(PCWSTR::null(), |dir| PCWSTR(dir.as_ptr())),
             nShow: SW_SHOWNORMAL.0,
             lpIDList: std::ptr::null_mut(),
             lpClass: PCWSTR::null(),
@@ -92,6 +102,107 @@ pub fn setcap_command(exe_path: &str) -> String {
     format!("sudo setcap cap_net_raw=ep '{exe_path}'")
 }
 
+/// Grants Irminsul permission to capture packets by running `setcap` through
+/// polkit, or through `sudo -A` when pkexec isn't installed and an askpass
+/// program is set up.
+///
+/// The process that runs the grant doesn't gain the capability, so a fresh
+/// copy of Irminsul is started to check it worked.
+#[cfg(target_os = "linux")]
+pub fn grant_cap_net_raw(exe_path: &str) -> anyhow::Result<()> {
+    use std::io::ErrorKind;
+    use std::process::Command;
+
+    use anyhow::{Context, bail};
+
+    // setcap lives in sbin, which often isn't on a desktop user's PATH.
+    let setcap = ["/usr/sbin/setcap", "/sbin/setcap", "/usr/bin/setcap"]
+        .into_iter()
+        .find(|path| std::path::Path::new(path).exists())
+        .unwrap_or("setcap");
+    let setcap_args = [setcap, "cap_net_raw=ep", exe_path];
+
+    let status = match Command::new("pkexec").args(setcap_args).status() {
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            if std::env::var_os("SUDO_ASKPASS").is_none() {
+                bail!("Neither pkexec nor a sudo askpass program is available");
+            }
+            Command::new("sudo")
+                .arg("-A")
+                .args(setcap_args)
+                .status()
+                .context("Unable to run sudo")?
+        }
+        result => result.context("Unable to run pkexec")?,
+    };
+    match status.code() {
+        Some(0) => (),
+        // pkexec's codes for the authentication dialog being dismissed and
+        // for there being no polkit agent to show it.
+        Some(126) => bail!("Authentication was cancelled"),
+        Some(127) => bail!("No polkit authentication agent is running"),
+        _ => bail!("setcap failed ({status})"),
+    }
+
+    let verified = Command::new(exe_path)
+        .arg("--check-capture-permissions")
+        .status()
+        .context("Unable to check the granted permissions")?;
+    if !verified.success() {
+        bail!(
+            "Irminsul still can't capture packets.  Its file system may not support capabilities"
+        );
+    }
+    Ok(())
+}
+
+/// "Grant now" button that grants capture permissions in the background, so
+/// the UI keeps drawing while polkit asks for a password.
+#[cfg(target_os = "linux")]
+#[derive(Default)]
+pub struct GrantButton {
+    task: Option<std::thread::JoinHandle<anyhow::Result<()>>>,
+    error: Option<String>,
+}
+
+#[cfg(target_os = "linux")]
+impl GrantButton {
+    /// Show the button, returning true once permissions have been granted
+    /// and checked.
+    pub fn ui(&mut self, ui: &mut egui::Ui, exe_path: &str) -> bool {
+        let mut granted = false;
+        if let Some(task) = self.task.take_if(|task| task.is_finished()) {
+            match task.join() {
+                Ok(Ok(())) => granted = true,
+                Ok(Err(e)) => self.error = Some(format!("{e:#}")),
+                Err(_) => self.error = Some("Granting permissions panicked".to_string()),
+            }
+        }
+
+        if self.task.is_some() {
+            ui.spinner();
+            ui.ctx()
+                .request_repaint_after(std::time::Duration::from_millis(100));
+        } else if ui.button("Grant now").clicked() {
+            let exe_path = exe_path.to_string();
+            self.error = None;
+            self.task = Some(std::thread::spawn(move || grant_cap_net_raw(&exe_path)));
+        }
+        granted
+    }
+
+    /// Why the last grant failed, with the fallback of running the command
+    /// by hand.
+    pub fn error_ui(&self, ui: &mut egui::Ui) {
+        if let Some(error) = &self.error {
+            ui.colored_label(
+                ui.visuals().error_fg_color,
+                format!("{error}.  Run the command in a terminal instead."),
+            );
+        }
+    }
+}
+
 #[cfg(unix)]
 pub fn is_root() -> bool {
     nix::unistd::geteuid().is_root()
@@ -110,32 +221,42 @@ pub fn has_cap_net_raw() -> bool {
     }
 }
 
+/// Whether Irminsul can capture packets itself.
 #[cfg(unix)]
-pub fn ensure_admin() {
+pub fn can_capture() -> bool {
     // We are happy if we are running as root or have CAP_NET_RAW
     if is_root() || has_cap_net_raw() {
-        return;
+        return true;
     }
 
     // On macOS, /dev/bpf access is sufficient
     #[cfg(target_os = "macos")]
     if std::fs::File::open("/dev/bpf0").is_ok() {
-        return;
+        return true;
     }
 
-    show_packet_capture_permissions_missing_dialog();
+    false
+}
+
+#[cfg(unix)]
+pub fn ensure_admin() {
+    if !can_capture() {
+        show_packet_capture_permissions_missing_dialog();
+    }
 }
 
 #[cfg(unix)]
 fn show_packet_capture_permissions_missing_dialog() {
     let options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default()
-            .with_inner_size([500.0, 200.0])
+            .with_inner_size([500.0, 300.0])
             .with_resizable(true),
         ..Default::default()
     };
 
     let exe_path = current_exe_path();
+    #[cfg(target_os = "linux")]
+    let mut grant_button = GrantButton::default();
 
     let _ = eframe::run_simple_native(
         "Irminsul requires packet capture permissions",
@@ -153,6 +274,10 @@ fn show_packet_capture_permissions_missing_dialog() {
                             setcap_command(&exe_path),
                             exe_path
                         ));
+                        if grant_button.ui(ui, &exe_path) {
+                            restart();
+                        }
+                        grant_button.error_ui(ui);
                     }
 
                     #[cfg(target_os = "macos")]
@@ -163,6 +288,15 @@ fn show_packet_capture_permissions_missing_dialog() {
                     ui.add_space(5.0);
                     ui.label("2. Run Irminsul as root (every time):");
                     ui.label(format!("sudo '{}'", exe_path));
+                    ui.add_space(5.0);
+                    ui.label("3. Feed Irminsul packets from a capturer that has the permissions:");
+                    ui.label(format!("'{}' --capture-backend pipe", exe_path));
+                    #[cfg(target_os = "linux")]
+                    {
+                        ui.add_space(5.0);
+                        ui.label("4. Install the capture helper once (see the README), which");
+                        ui.label("keeps its permissions across updates.");
+                    }
                     ui.add_space(10.0);
                     ui.label("Rerun Irminsul with --no-admin if you wish to proceed without packet capture")
                 });
@@ -183,3 +317,16 @@ fn show_packet_capture_permissions_missing_dialog() {
 
     std::process::exit(1);
 }
+
+/// Start a new copy of Irminsul with the same arguments and exit this one.
+#[cfg(target_os = "linux")]
+fn restart() -> ! {
+    if let Err(e) = std::process::Command::new(current_exe_path())
+        .args(std::env::args_os().skip(1))
+        .spawn()
+    {
+        tracing::error!("unable to restart Irminsul: {e}");
+        std::process::exit(1);
+    }
+    std::process::exit(0);
+}
@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::fmt::Display;
 use std::fs::File;
 use std::io::{BufWriter, Write};
@@ -5,8 +6,8 @@ use std::path::PathBuf;
 use std::thread;
 use std::time::Instant;
 
-use anyhow::{Context as _, Result, anyhow};
-use chrono::Local;
+use anyhow::{Context as _, Result, anyhow, bail};
+use chrono::{DateTime, Local};
 use egui::{
     Button, Color32, Context, DragValue, Id, Key, KeyboardShortcut, Modal, Modifiers, OpenUrl,
     PointerButton, RichText, Sense, ViewportCommand,
@@ -16,12 +17,21 @@ use egui_notify::Toasts;
 use serde::{Deserialize, Serialize};
 use tokio::sync::{mpsc, oneshot, watch};
 
-use crate::monitor::Monitor;
+use crate::artifacts::{
+    self, ArtifactAnalysis, CleanupAction, CleanupRecommendation, MAX_ROLLS, ScoreMethod, SortKey,
+};
+use crate::capture::{CaptureOverrides, CaptureSettings, Interface, PortSet};
+use crate::export::{Export, ExportFormat};
+use crate::monitor::{self, Monitor};
+use crate::planner::{Goal, Plan};
 use crate::player_data::ExportSettings;
-use crate::update::check_for_app_update;
+use crate::spreadsheet::{self, Sheet};
+use crate::update::{
+    self, GitHubReleases, UpdateArgs, UpdateChannel, UpdateSettings, check_for_app_update,
+};
 use crate::{
-    AppState, ConfirmationType, Message, ReloadHandle, State, TracingLevel, admin, capture,
-    open_log_dir, wish,
+    AppState, CaptureHealth, ConfirmationType, Message, ReloadHandle, SessionState, State,
+    TracingLevel, UpdateProgress, admin, capture, keys, open_log_dir, wish,
 };
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -32,6 +42,19 @@ pub struct SavedAppState {
     log_raw_packets: bool,
     #[serde(default)]
     tracing_level: TracingLevel,
+    #[serde(default)]
+    planner_goals: HashMap<String, Goal>,
+    #[serde(default)]
+    score_settings: artifacts::ScoreSettings,
+    #[serde(default)]
+    export_format: ExportFormat,
+    /// Game data file to load instead of the built-in or downloaded data.
+    #[serde(default)]
+    game_data_path: Option<PathBuf>,
+    #[serde(default)]
+    capture_settings: CaptureSettings,
+    #[serde(default)]
+    update_settings: UpdateSettings,
 }
 
 impl Default for SavedAppState {
@@ -56,6 +79,12 @@ impl Default for SavedAppState {
             auto_start_capture: false,
             log_raw_packets: false,
             tracing_level: Default::default(),
+            planner_goals: HashMap::new(),
+            score_settings: Default::default(),
+            export_format: Default::default(),
+            game_data_path: None,
+            capture_settings: CaptureSettings::default(),
+            update_settings: UpdateSettings::default(),
         }
     }
 }
@@ -67,6 +96,12 @@ enum OptimizerExportTarget {
     File,
 }
 
+#[derive(Clone, Copy, Debug)]
+enum PlanExportFormat {
+    Json,
+    Csv,
+}
+
 pub struct IrminsulApp {
     ui_message_tx: mpsc::UnboundedSender<Message>,
     state_rx: watch::Receiver<AppState>,
@@ -82,12 +117,59 @@ pub struct IrminsulApp {
     capture_settings_open: bool,
 
     optimizer_settings_open: bool,
-    optimizer_export_rx: Option<oneshot::Receiver<Result<String>>>,
+    optimizer_export_rx: Option<oneshot::Receiver<Result<Export>>>,
     optimizer_save_dialog: Option<FileDialog>,
     optimizer_save_path: Option<PathBuf>,
     optimizer_export_target: OptimizerExportTarget,
 
+    planner_open: bool,
+    planner_rx: Option<oneshot::Receiver<Plan>>,
+    plan: Option<Plan>,
+    planner_save_dialog: Option<FileDialog>,
+    planner_export_format: PlanExportFormat,
+
+    artifacts_open: bool,
+    artifact_settings_open: bool,
+    artifact_analysis_rx: Option<oneshot::Receiver<Vec<ArtifactAnalysis>>>,
+    artifact_analysis: Vec<ArtifactAnalysis>,
+    artifact_sort: (SortKey, bool),
+    /// Character whose build weights are being edited, or empty for the
+    /// default weights.
+    artifact_weights_character: String,
+    artifact_cleanup_open: bool,
+    artifact_cleanup_rx: Option<oneshot::Receiver<Vec<CleanupRecommendation>>>,
+    artifact_cleanup: Vec<CleanupRecommendation>,
+
+    /// Game data file given with `--game-data`, which overrides the one
+    /// picked in power tools.
+    game_data_arg: Option<PathBuf>,
+    game_data_dialog: Option<FileDialog>,
+    /// Game data file picked in power tools, being checked that it loads.
+    game_data_check_rx: Option<oneshot::Receiver<(PathBuf, Result<()>)>>,
+    /// When the last game data error was shown.
+    game_data_error_shown: Option<Instant>,
+
+    /// When the last unknown id report was shown.
+    unknown_ids_shown: Option<Instant>,
+
+    capture_backend: capture::BackendType,
+    /// Capture settings given on the command line, which override the ones
+    /// in the capture settings modal.
+    capture_overrides: CaptureOverrides,
+    /// Interfaces listed in the capture settings modal, loaded when it opens.
+    capture_interfaces: Option<std::result::Result<Vec<Interface>, String>>,
+    /// When the last capture errors were shown.
+    capture_errors_shown: Option<Instant>,
+    /// Text of the capture settings being edited, kept separately so invalid
+    /// input can be shown while it's fixed.
+    capture_ports_text: String,
+    capture_filter_text: String,
+
     restarting: bool,
+    /// Whether a freshly installed update has been marked as started.
+    update_marked_healthy: bool,
+    #[cfg(target_os = "linux")]
+    grant_button: admin::GrantButton,
 
     saved_state: SavedAppState,
 }
@@ -113,6 +195,9 @@ fn start_async_runtime(
     egui_ctx: Context,
     log_packets_rx: watch::Receiver<bool>,
     capture_backend: capture::BackendType,
+    game_data_path: Option<PathBuf>,
+    keys_path: Option<PathBuf>,
+    update_settings: Option<UpdateSettings>,
 ) -> (
     mpsc::UnboundedSender<Message>,
     watch::Receiver<AppState>,
@@ -129,10 +214,20 @@ fn start_async_runtime(
         let rt = tokio::runtime::Runtime::new().unwrap();
 
         rt.block_on(async {
-            // Before starting the monitor, check for updates if not in debug mode
-            tracing::info!("Checking for update");
-            if let Err(e) = check_for_app_update(&state_tx, &mut ui_message_rx).await {
-                tracing::error!("error checking for update: {e}");
+            // Before starting the monitor, check for updates unless they're
+            // turned off with --no-update.
+            if let Some(update_settings) = update_settings {
+                tracing::info!("Checking for update");
+                if let Err(e) = check_for_app_update(
+                    &state_tx,
+                    &mut ui_message_rx,
+                    &GitHubReleases::default(),
+                    &update_settings,
+                )
+                .await
+                {
+                    tracing::error!("error checking for update: {e}");
+                }
             }
 
             // Check for wish URL
@@ -160,6 +255,8 @@ fn start_async_runtime(
                 ui_message_rx,
                 log_packets_rx,
                 capture_backend,
+                game_data_path,
+                keys_path,
             )
             .await
             {
@@ -181,30 +278,56 @@ impl IrminsulApp {
         cc: &eframe::CreationContext<'_>,
         mut tracing_reload_handle: ReloadHandle,
         capture_backend: capture::BackendType,
+        game_data_arg: Option<PathBuf>,
+        keys_path: Option<PathBuf>,
+        capture_overrides: CaptureOverrides,
+        update_args: UpdateArgs,
     ) -> Self {
         egui_extras::install_image_loaders(&cc.egui_ctx);
         egui_material_icons::initialize(&cc.egui_ctx);
 
-        let saved_state: SavedAppState = if let Some(storage) = cc.storage {
+        let mut saved_state: SavedAppState = if let Some(storage) = cc.storage {
             eframe::get_value(storage, eframe::APP_KEY).unwrap_or_default()
         } else {
             Default::default()
         };
+        // Don't offer the update that just failed to start again straight away.
+        if let Some(version) = &update_args.rolled_back_from {
+            saved_state.update_settings.skipped_version = Some(version.clone());
+        }
 
         tracing_reload_handle.set_filter(saved_state.tracing_level.get_filter());
         let (log_packets_tx, log_packets_rx) = watch::channel(saved_state.log_raw_packets);
-        let (ui_message_tx, state_rx, wish_url_rx) =
-            start_async_runtime(cc.egui_ctx.clone(), log_packets_rx, capture_backend);
+        let game_data_path = game_data_arg
+            .clone()
+            .or_else(|| saved_state.game_data_path.clone());
+        let (ui_message_tx, state_rx, wish_url_rx) = start_async_runtime(
+            cc.egui_ctx.clone(),
+            log_packets_rx,
+            capture_backend,
+            game_data_path,
+            keys_path,
+            (!update_args.no_update).then(|| saved_state.update_settings.clone()),
+        );
 
-        if saved_state.auto_start_capture {
-            if let Err(e) = ui_message_tx.send(Message::StartCapture) {
-                tracing::error!("Failed to send auto start message: {e}");
-            }
+        let mut toasts = Toasts::default().with_anchor(egui_notify::Anchor::BottomLeft);
+        if let Some(version) = update_args.rolled_back_from {
+            tracing::warn!("Irminsul {version} failed to start and was rolled back");
+            toasts
+                .warning(format!(
+                    "Irminsul {version} failed to start, so the previous version was \
+                     restored. {version} is skipped until cleared in Power Tools."
+                ))
+                .duration(None);
         }
 
-        let toasts = Toasts::default().with_anchor(egui_notify::Anchor::BottomLeft);
-
-        Self {
+        let capture_ports_text = saved_state.capture_settings.ports.to_string();
+        let capture_filter_text = saved_state
+            .capture_settings
+            .filter
+            .clone()
+            .unwrap_or_default();
+        let mut app = Self {
             saved_state,
             ui_message_tx,
             log_packets_tx,
@@ -218,10 +341,44 @@ impl IrminsulApp {
             optimizer_save_dialog: None,
             optimizer_save_path: None,
             optimizer_export_target: OptimizerExportTarget::None,
+            planner_open: false,
+            planner_rx: None,
+            plan: None,
+            planner_save_dialog: None,
+            planner_export_format: PlanExportFormat::Json,
+            artifacts_open: false,
+            artifact_settings_open: false,
+            artifact_analysis_rx: None,
+            artifact_analysis: Vec::new(),
+            artifact_sort: (SortKey::Score, true),
+            artifact_weights_character: String::new(),
+            artifact_cleanup_open: false,
+            artifact_cleanup_rx: None,
+            artifact_cleanup: Vec::new(),
+            game_data_arg,
+            game_data_dialog: None,
+            game_data_check_rx: None,
+            game_data_error_shown: None,
+            unknown_ids_shown: None,
+            capture_backend,
+            capture_overrides,
+            capture_interfaces: None,
+            capture_errors_shown: None,
+            capture_ports_text,
+            capture_filter_text,
             restarting: false,
+            update_marked_healthy: false,
+            #[cfg(target_os = "linux")]
+            grant_button: admin::GrantButton::default(),
             state_rx,
             wish_url_rx,
+        };
+
+        if app.saved_state.auto_start_capture {
+            app.start_capture();
         }
+
+        app
     }
 }
 
@@ -233,6 +390,11 @@ impl eframe::App for IrminsulApp {
 
     /// Called each time the UI needs repainting, which may be many times per second.
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        if !self.update_marked_healthy {
+            update::mark_update_healthy();
+            self.update_marked_healthy = true;
+        }
+
         ctx.style_mut(|style| {
             style.interaction.selectable_labels = false;
             style.interaction.tooltip_delay = 0.25;
@@ -242,6 +404,12 @@ impl eframe::App for IrminsulApp {
         if let Some(optimizer_save_dialog) = &mut self.optimizer_save_dialog {
             optimizer_save_dialog.update(ctx);
         }
+        if let Some(planner_save_dialog) = &mut self.planner_save_dialog {
+            planner_save_dialog.update(ctx);
+        }
+        if let Some(game_data_dialog) = &mut self.game_data_dialog {
+            game_data_dialog.update(ctx);
+        }
 
         egui::CentralPanel::default().show(ctx, |ui| {
             ui.with_layout(egui::Layout::top_down(egui::Align::LEFT), |ui| {
@@ -293,10 +461,10 @@ impl eframe::App for IrminsulApp {
                     ui.vertical(|ui| match state.state {
                         State::Starting => (),
                         State::CheckingForUpdate => self.checking_for_update_ui(ui),
-                        State::WaitingForUpdateConfirmation(status) => {
-                            self.waiting_for_update_confirmation_ui(ui, status)
+                        State::WaitingForUpdateConfirmation { version, notes } => {
+                            self.waiting_for_update_confirmation_ui(ui, version, notes)
                         }
-                        State::Updating => self.updating_ui(ui),
+                        State::Updating(progress) => self.updating_ui(ui, progress),
                         State::Updated { needs_caps } => self.updated_ui(ui, needs_caps),
                         State::CheckingForData => self.checking_for_data_ui(ui),
                         State::WaitingForDownloadConfirmation(confirmation_type) => {
@@ -393,11 +561,21 @@ impl IrminsulApp {
         });
     }
 
-    fn waiting_for_update_confirmation_ui(&self, ui: &mut egui::Ui, version: String) {
+    fn waiting_for_update_confirmation_ui(
+        &mut self,
+        ui: &mut egui::Ui,
+        version: String,
+        notes: Option<String>,
+    ) {
         ui.label(format!(
             "Update {} available.  Download and install?",
             version
         ));
+        if let Some(notes) = notes {
+            egui::ScrollArea::vertical()
+                .max_height(150.)
+                .show(ui, |ui| ui.label(notes));
+        }
 
         ui.horizontal(|ui| {
             if ui.add(egui::Button::new("Yes")).clicked() {
@@ -410,14 +588,55 @@ impl IrminsulApp {
                     tracing::error!("Unable to send UI message: {e}");
                 }
             }
+            if ui
+                .add(egui::Button::new("Skip this version"))
+                .on_hover_text("Don't offer this version again")
+                .clicked()
+            {
+                self.saved_state.update_settings.skipped_version = Some(version.clone());
+                if let Err(e) = self.ui_message_tx.send(Message::UpdateCanceled) {
+                    tracing::error!("Unable to send UI message: {e}");
+                }
+            }
         });
     }
 
-    fn updating_ui(&self, ui: &mut egui::Ui) {
-        ui.horizontal(|ui| {
-            ui.label("Downloading and updating...".to_string());
-            ui.spinner();
-        });
+    fn updating_ui(&self, ui: &mut egui::Ui, progress: UpdateProgress) {
+        const MB: f32 = 1024. * 1024.;
+
+        ui.label("Downloading and updating...".to_string());
+        let downloaded = progress.downloaded as f32 / MB;
+        match progress.total {
+            Some(total) if total > 0 => {
+                ui.add(
+                    egui::ProgressBar::new(progress.downloaded as f32 / total as f32)
+                        .desired_width(250.)
+                        .text(format!("{downloaded:.1} / {:.1} MB", total as f32 / MB)),
+                );
+            }
+            _ => {
+                ui.horizontal(|ui| {
+                    ui.label(format!("{downloaded:.1} MB"));
+                    ui.spinner();
+                });
+            }
+        }
+        if let Some(error) = progress.error {
+            ui.colored_label(
+                ui.visuals().warn_fg_color,
+                format!(
+                    "{} Download interrupted, retrying (attempt {})",
+                    egui_material_icons::icons::ICON_SYNC,
+                    progress.attempt
+                ),
+            )
+            .on_hover_text(error);
+        }
+        if ui.button("Cancel").clicked() {
+            if let Err(e) = self.ui_message_tx.send(Message::UpdateCanceled) {
+                tracing::error!("Unable to send UI message: {e}");
+            }
+        }
     }
 
     /// Relaunch Irminsul and close this instance.
@@ -467,10 +686,19 @@ impl IrminsulApp {
             if ui.button("Copy").clicked() {
                 ui.ctx().copy_text(command.clone());
             }
+            #[cfg(target_os = "linux")]
+            if self.grant_button.ui(ui, &exe_path) && !self.restarting {
+                self.restart(ui);
+            }
             if ui.button("Restart").clicked() && !self.restarting {
                 self.restart(ui);
             }
         });
+        #[cfg(target_os = "linux")]
+        self.grant_button.error_ui(ui);
+        ui.add_space(5.0);
+        ui.label("Installing the capture helper (see the README) stops");
+        ui.label("updates from resetting them.");
     }
 
     fn checking_for_data_ui(&self, ui: &mut egui::Ui) {
@@ -484,16 +712,27 @@ impl IrminsulApp {
         ui: &mut egui::Ui,
         confirmation_type: ConfirmationType,
     ) {
-        let label = match confirmation_type {
-            ConfirmationType::Initial => "Irminsul needs to download initial data",
-            ConfirmationType::Update => "New data available",
+        let (label, skip_label) = match confirmation_type {
+            ConfirmationType::Initial => (
+                "Download the latest game data?  The data Irminsul was built with may be \
+                 out of date",
+                "Use built-in data",
+            ),
+            ConfirmationType::Update => ("New game data available", "Skip"),
         };
         ui.label(label.to_string());
-        if ui.add(egui::Button::new("Download")).clicked() {
-            if let Err(e) = self.ui_message_tx.send(Message::DownloadAcknowledged) {
-                tracing::error!("Unable to send UI message{e}");
+        ui.horizontal(|ui| {
+            if ui.add(egui::Button::new("Download")).clicked() {
+                if let Err(e) = self.ui_message_tx.send(Message::DownloadAcknowledged) {
+                    tracing::error!("Unable to send UI message{e}");
+                }
             }
-        }
+            if ui.add(egui::Button::new(skip_label)).clicked() {
+                if let Err(e) = self.ui_message_tx.send(Message::DownloadSkipped) {
+                    tracing::error!("Unable to send UI message{e}");
+                }
+            }
+        });
     }
 
     fn load_data_ui(&self, ui: &mut egui::Ui) {
@@ -504,6 +743,29 @@ impl IrminsulApp {
     }
 
     fn main_ui(&mut self, ui: &mut egui::Ui, app_state: &AppState) {
+        if app_state.updated.unknown_ids_updated != self.unknown_ids_shown {
+            self.unknown_ids_shown = app_state.updated.unknown_ids_updated;
+            self.toasts.warning(format!(
+                "Game data is missing {} from this capture, so they are left out of exports. \
+                 A report was saved to the log folder.",
+                app_state.unknown_ids.summary()
+            ));
+        }
+
+        if app_state.updated.capture_errors_updated != self.capture_errors_shown {
+            self.capture_errors_shown = app_state.updated.capture_errors_updated;
+            for error in &app_state.capture_errors {
+                self.toasts.error(error.clone());
+            }
+        }
+
+        if app_state.updated.game_data_error_updated != self.game_data_error_shown {
+            self.game_data_error_shown = app_state.updated.game_data_error_updated;
+            if let Some(error) = &app_state.game_data_error {
+                self.toasts.error(error.clone()).duration(None);
+            }
+        }
+
         if self.capture_settings_open {
             let modal = Modal::new(Id::new("Capture Settings")).show(ui.ctx(), |ui| {
                 self.capture_settings_modal(ui);
@@ -521,10 +783,50 @@ impl IrminsulApp {
                 self.optimizer_settings_open = false;
             }
         }
+
+        if self.planner_open {
+            let modal = Modal::new(Id::new("Material Planner")).show(ui.ctx(), |ui| {
+                self.planner_modal(ui);
+            });
+            if modal.should_close() {
+                self.planner_open = false;
+            }
+        }
+
+        if self.artifacts_open {
+            let modal = Modal::new(Id::new("Artifacts")).show(ui.ctx(), |ui| {
+                self.artifacts_modal(ui);
+            });
+            if modal.should_close() {
+                self.artifacts_open = false;
+            }
+        }
+
+        if self.artifact_settings_open {
+            let modal = Modal::new(Id::new("Artifact Scoring Settings")).show(ui.ctx(), |ui| {
+                self.artifact_settings_modal(ui);
+            });
+            if modal.should_close() {
+                self.artifact_settings_open = false;
+            }
+        }
+
+        if self.artifact_cleanup_open {
+            let modal = Modal::new(Id::new("Artifact Cleanup")).show(ui.ctx(), |ui| {
+                self.artifact_cleanup_modal(ui);
+            });
+            if modal.should_close() {
+                self.artifact_cleanup_open = false;
+            }
+        }
         self.capture_ui(ui, app_state);
         ui.separator();
         self.genshin_optimizer_ui(ui, app_state);
         ui.separator();
+        self.planner_ui(ui, app_state);
+        ui.separator();
+        self.artifacts_ui(ui, app_state);
+        ui.separator();
         self.wish_ui(ui);
         ui.separator();
         self.achievement_ui(ui, app_state);
@@ -543,6 +845,7 @@ impl IrminsulApp {
                         .clicked()
                     {
                         self.capture_settings_open = true;
+                        self.capture_interfaces = None;
                     }
 
                     if app_state.capturing {
@@ -553,7 +856,7 @@ impl IrminsulApp {
                         .button(egui_material_icons::icons::ICON_PLAY_ARROW)
                         .clicked()
                     {
-                        let _ = self.ui_message_tx.send(Message::StartCapture);
+                        self.start_capture();
                     }
                 },
             );
@@ -567,6 +870,70 @@ impl IrminsulApp {
                 Self::data_state(ui, "Characters", app_state.updated.characters_updated);
                 Self::data_state(ui, "Achievements", app_state.updated.achievements_updated);
             });
+        if app_state.capturing {
+            match &app_state.capture_health {
+                CaptureHealth::Running => (),
+                CaptureHealth::Restarting { error, .. } => {
+                    ui.colored_label(
+                        ui.visuals().warn_fg_color,
+                        format!(
+                            "{} {}",
+                            egui_material_icons::icons::ICON_SYNC,
+                            app_state.capture_health
+                        ),
+                    )
+                    .on_hover_text(error);
+                }
+                CaptureHealth::FallenBack(_) => {
+                    ui.colored_label(
+                        ui.visuals().warn_fg_color,
+                        format!(
+                            "{} {}",
+                            egui_material_icons::icons::ICON_WARNING,
+                            app_state.capture_health
+                        ),
+                    )
+                    .on_hover_text(format!(
+                        "The {:?} backend couldn't be started",
+                        self.capture_backend
+                    ));
+                }
+            }
+            let session = app_state.session;
+            let (icon, color) = match session {
+                SessionState::MissedHandshake => (
+                    egui_material_icons::icons::ICON_WARNING,
+                    ui.visuals().warn_fg_color,
+                ),
+                SessionState::PlayerDataReceived => (
+                    egui_material_icons::icons::ICON_CHECK_CIRCLE,
+                    Color32::from_hex("#00ab3f").unwrap(),
+                ),
+                SessionState::Ended(_) => (
+                    egui_material_icons::icons::ICON_LINK_OFF,
+                    ui.visuals().weak_text_color(),
+                ),
+                _ => (
+                    egui_material_icons::icons::ICON_PENDING,
+                    ui.visuals().text_color(),
+                ),
+            };
+            ui.colored_label(color, format!("{icon} {session}"));
+            if app_state.dropped_packets > 0 {
+                ui.colored_label(
+                    ui.visuals().warn_fg_color,
+                    format!(
+                        "{} {} packets dropped",
+                        egui_material_icons::icons::ICON_WARNING,
+                        app_state.dropped_packets
+                    ),
+                )
+                .on_hover_text(
+                    "Packets arrived faster than they could be processed. \
+                     If data is missing, stop other network heavy programs and relog.",
+                );
+            }
+        }
     }
 
     fn data_state(ui: &mut egui::Ui, source: &str, last_updated: Option<Instant>) {
@@ -583,11 +950,12 @@ impl IrminsulApp {
     fn genshin_optimizer_ui(&mut self, ui: &mut egui::Ui, app_state: &AppState) {
         self.optimizer_handle_export(ui).toast_error(self);
 
+        let exporter = self.saved_state.export_format.exporter();
         ui.vertical(|ui| {
             egui::Sides::new().show(
                 ui,
                 |ui| {
-                    Self::section_header(ui, "Genshin Optimizer");
+                    Self::section_header(ui, exporter.name());
                 },
                 |ui| {
                     if ui
@@ -607,12 +975,18 @@ impl IrminsulApp {
                                 .clicked()
                             {
                                 let now = Local::now();
+                                let file_types = exporter.file_types();
                                 let mut optimizer_save_dialog = FileDialog::new()
-                                    .add_file_filter_extensions("JSON files", vec!["json"])
                                     .default_file_name(&format!(
-                                        "genshin_export_{}.json",
-                                        now.format("%Y-%m-%d_%H-%M")
+                                        "{}_{}.{}",
+                                        exporter.file_prefix(),
+                                        now.format("%Y-%m-%d_%H-%M"),
+                                        file_types[0].1
                                     ));
+                                for (name, extension) in file_types {
+                                    optimizer_save_dialog = optimizer_save_dialog
+                                        .add_file_filter_extensions(name, vec![extension]);
+                                }
                                 optimizer_save_dialog.save_file();
                                 self.optimizer_save_dialog = Some(optimizer_save_dialog);
                             }
@@ -624,9 +998,10 @@ impl IrminsulApp {
                                 self.genshin_optimizer_request_export(OptimizerExportTarget::File);
                             }
 
-                            if ui
-                                .button(egui_material_icons::icons::ICON_CONTENT_PASTE_GO)
-                                .clicked()
+                            if exporter.can_copy()
+                                && ui
+                                    .button(egui_material_icons::icons::ICON_CONTENT_PASTE_GO)
+                                    .clicked()
                             {
                                 self.genshin_optimizer_request_export(
                                     OptimizerExportTarget::Clipboard,
@@ -641,7 +1016,8 @@ impl IrminsulApp {
 
     fn genshin_optimizer_request_export(&mut self, target: OptimizerExportTarget) {
         let (tx, rx) = oneshot::channel();
-        let _ = self.ui_message_tx.send(Message::ExportGenshinOptimizer(
+        let _ = self.ui_message_tx.send(Message::Export(
+            self.saved_state.export_format,
             self.saved_state.export_settings.clone(),
             tx,
         ));
@@ -649,6 +1025,563 @@ impl IrminsulApp {
         self.optimizer_export_rx = Some(rx);
     }
 
+    fn planner_ui(&mut self, ui: &mut egui::Ui, app_state: &AppState) {
+        if let Some(planner_save_dialog) = &mut self.planner_save_dialog
+            && let Some(path) = planner_save_dialog.take_picked()
+        {
+            self.planner_save_to_file(path).toast_error(self);
+        }
+
+        ui.vertical(|ui| {
+            egui::Sides::new().show(
+                ui,
+                |ui| {
+                    Self::section_header(ui, "Material Planner");
+                },
+                |ui| {
+                    ui.add_enabled_ui(
+                        app_state.updated.characters_updated.is_some()
+                            && app_state.updated.items_updated.is_some(),
+                        |ui| {
+                            if ui
+                                .button(egui_material_icons::icons::ICON_CALCULATE)
+                                .clicked()
+                            {
+                                self.planner_request_plan();
+                                self.planner_open = true;
+                            }
+                        },
+                    );
+                },
+            );
+        });
+    }
+
+    fn planner_request_plan(&mut self) {
+        let (tx, rx) = oneshot::channel();
+        let _ = self
+            .ui_message_tx
+            .send(Message::Plan(self.saved_state.planner_goals.clone(), tx));
+        self.planner_rx = Some(rx);
+    }
+
+    fn planner_modal(&mut self, ui: &mut egui::Ui) {
+        if let Some(rx) = self.planner_rx.take()
+            && let Some(plan) = rx.blocking_recv().toast_error(self)
+        {
+            self.plan = Some(plan);
+        }
+
+        ui.set_width(700.0);
+        ui.heading("Material Planner");
+        ui.separator();
+
+        let Some(plan) = self.plan.clone() else {
+            ui.spinner();
+            return;
+        };
+
+        let mut goals_changed = false;
+        egui::ScrollArea::vertical()
+            .max_height(220.)
+            .show(ui, |ui| {
+                egui::Grid::new("planner_goals")
+                    .striped(true)
+                    .num_columns(7)
+                    .show(ui, |ui| {
+                        for header in [
+                            "Character",
+                            "Level",
+                            "Ascension",
+                            "Auto",
+                            "Skill",
+                            "Burst",
+                            "Mora",
+                        ] {
+                            ui.label(header);
+                        }
+                        ui.end_row();
+
+                        for character in &plan.characters {
+                            let current = &character.current;
+                            let mut goal = character.goal;
+                            ui.label(&current.key);
+                            for (from, to, max) in [
+                                (current.level, &mut goal.level, 90),
+                                (current.ascension, &mut goal.ascension, 6),
+                                (current.talent.auto, &mut goal.auto, 10),
+                                (current.talent.skill, &mut goal.skill, 10),
+                                (current.talent.burst, &mut goal.burst, 10),
+                            ] {
+                                ui.horizontal(|ui| {
+                                    ui.label(format!("{from} →"));
+                                    ui.add(DragValue::new(to).range(from..=max));
+                                });
+                            }
+                            ui.label(character.cost.mora.to_string());
+                            ui.end_row();
+
+                            if goal != character.goal {
+                                self.saved_state
+                                    .planner_goals
+                                    .insert(current.key.clone(), goal);
+                                goals_changed = true;
+                            }
+                        }
+                    });
+            });
+        if goals_changed {
+            self.planner_request_plan();
+        }
+
+        ui.separator();
+        egui::Grid::new("planner_totals")
+            .striped(true)
+            .num_columns(3)
+            .show(ui, |ui| {
+                ui.label("");
+                ui.label("Needed");
+                ui.label("Missing");
+                ui.end_row();
+
+                let (total, missing) = (&plan.total, &plan.missing);
+                for (name, total, missing) in [
+                    ("Mora", total.mora, missing.mora),
+                    ("Hero's Wit", total.heros_wits(), missing.heros_wits()),
+                    (
+                        "Boss drops",
+                        total.boss_drops.into(),
+                        missing.boss_drops.into(),
+                    ),
+                    (
+                        "Teachings",
+                        total.talent_books[0].into(),
+                        missing.talent_books[0].into(),
+                    ),
+                    (
+                        "Guides",
+                        total.talent_books[1].into(),
+                        missing.talent_books[1].into(),
+                    ),
+                    (
+                        "Philosophies",
+                        total.talent_books[2].into(),
+                        missing.talent_books[2].into(),
+                    ),
+                    (
+                        "Weekly boss drops",
+                        total.weekly_boss_drops.into(),
+                        missing.weekly_boss_drops.into(),
+                    ),
+                    ("Crowns", total.crowns.into(), missing.crowns.into()),
+                ] {
+                    ui.label(name);
+                    ui.label(total.to_string());
+                    ui.label(missing.to_string());
+                    ui.end_row();
+                }
+            });
+        ui.label(
+            "Boss drops, talent books and weekly boss drops are counted across all kinds, \
+             so the ones owned aren't taken off what's missing.",
+        );
+
+        ui.separator();
+        egui::Sides::new().show(
+            ui,
+            |ui| {
+                if ui.button("Save JSON").clicked() {
+                    self.planner_open_save_dialog(PlanExportFormat::Json);
+                }
+                if ui.button("Save CSV").clicked() {
+                    self.planner_open_save_dialog(PlanExportFormat::Csv);
+                }
+            },
+            |ui| {
+                if ui.button("Ok").clicked() {
+                    ui.close()
+                }
+            },
+        );
+    }
+
+    fn planner_open_save_dialog(&mut self, format: PlanExportFormat) {
+        let (name, extension) = match format {
+            PlanExportFormat::Json => ("JSON files", "json"),
+            PlanExportFormat::Csv => ("CSV files", "csv"),
+        };
+        let now = Local::now();
+        let mut planner_save_dialog = FileDialog::new()
+            .add_file_filter_extensions(name, vec![extension])
+            .default_file_name(&format!(
+                "genshin_plan_{}.{extension}",
+                now.format("%Y-%m-%d_%H-%M")
+            ));
+        planner_save_dialog.save_file();
+        self.planner_save_dialog = Some(planner_save_dialog);
+        self.planner_export_format = format;
+    }
+
+    fn planner_save_to_file(&mut self, path: PathBuf) -> Result<()> {
+        let plan = self
+            .plan
+            .as_ref()
+            .ok_or_else(|| anyhow!("No plan to save"))?;
+        let contents = match self.planner_export_format {
+            PlanExportFormat::Json => plan.to_json()?,
+            PlanExportFormat::Csv => plan.to_csv()?,
+        };
+
+        let file = File::create(&path).with_context(|| format!("Unable to open file {path:?}"))?;
+        let mut writer = BufWriter::new(file);
+        writer.write_all(contents.as_bytes())?;
+
+        self.toasts.info("Material plan saved to file");
+        Ok(())
+    }
+
+    fn artifacts_ui(&mut self, ui: &mut egui::Ui, app_state: &AppState) {
+        ui.vertical(|ui| {
+            egui::Sides::new().show(
+                ui,
+                |ui| {
+                    Self::section_header(ui, "Artifacts");
+                },
+                |ui| {
+                    if ui
+                        .button(egui_material_icons::icons::ICON_SETTINGS)
+                        .clicked()
+                    {
+                        self.artifacts_request_analysis();
+                        self.artifact_settings_open = true;
+                    }
+
+                    ui.add_enabled_ui(app_state.updated.items_updated.is_some(), |ui| {
+                        if ui
+                            .button(egui_material_icons::icons::ICON_ANALYTICS)
+                            .clicked()
+                        {
+                            self.artifacts_request_analysis();
+                            self.artifacts_open = true;
+                        }
+
+                        if ui
+                            .button(egui_material_icons::icons::ICON_CLEANING_SERVICES)
+                            .clicked()
+                        {
+                            self.artifacts_request_cleanup();
+                            self.artifact_cleanup_open = true;
+                        }
+                    });
+                },
+            );
+        });
+    }
+
+    fn artifacts_request_analysis(&mut self) {
+        let (tx, rx) = oneshot::channel();
+        let _ = self.ui_message_tx.send(Message::AnalyzeArtifacts(
+            self.saved_state.score_settings.clone(),
+            tx,
+        ));
+        self.artifact_analysis_rx = Some(rx);
+    }
+
+    fn artifacts_request_cleanup(&mut self) {
+        let (tx, rx) = oneshot::channel();
+        let _ = self.ui_message_tx.send(Message::RecommendCleanup(
+            self.saved_state.score_settings.clone(),
+            tx,
+        ));
+        self.artifact_cleanup_rx = Some(rx);
+    }
+
+    fn artifacts_handle_analysis(&mut self) {
+        if let Some(rx) = self.artifact_analysis_rx.take()
+            && let Some(analysis) = rx.blocking_recv().toast_error(self)
+        {
+            self.artifact_analysis = analysis;
+            let (key, descending) = self.artifact_sort;
+            artifacts::sort(&mut self.artifact_analysis, key, descending);
+        }
+    }
+
+    fn artifacts_modal(&mut self, ui: &mut egui::Ui) {
+        self.artifacts_handle_analysis();
+
+        ui.set_width(750.0);
+        ui.heading("Artifacts");
+        ui.separator();
+
+        let mut sort = self.artifact_sort;
+        egui::ScrollArea::vertical()
+            .max_height(300.)
+            .show(ui, |ui| {
+                egui::Grid::new("artifact_list")
+                    .striped(true)
+                    .num_columns(8)
+                    .show(ui, |ui| {
+                        for (name, key) in [
+                            ("Set", Some(SortKey::Set)),
+                            ("Slot", Some(SortKey::Slot)),
+                            ("Main stat", None),
+                            ("Level", Some(SortKey::Level)),
+                            ("Location", Some(SortKey::Location)),
+                            ("Substats", None),
+                            ("CV", Some(SortKey::CritValue)),
+                            ("Score", Some(SortKey::Score)),
+                        ] {
+                            let Some(key) = key else {
+                                ui.label(name);
+                                continue;
+                            };
+                            let (sort_key, descending) = sort;
+                            let label = match (sort_key == key, descending) {
+                                (true, true) => format!("{name} ⬇"),
+                                (true, false) => format!("{name} ⬆"),
+                                (false, _) => name.to_string(),
+                            };
+                            if ui.add(Button::new(label).frame(false)).clicked() {
+                                sort = (key, sort_key != key || !descending);
+                            }
+                        }
+                        ui.end_row();
+
+                        for analysis in &self.artifact_analysis {
+                            let artifact = &analysis.artifact;
+                            ui.label(&artifact.set_key);
+                            ui.label(&artifact.slot_key);
+                            ui.label(&artifact.main_stat_key);
+                            ui.label(format!("+{}", artifact.level));
+                            ui.label(&artifact.location);
+                            ui.vertical(|ui| {
+                                for substat in &analysis.substats {
+                                    let tiers: Vec<String> =
+                                        substat.tiers.iter().map(u32::to_string).collect();
+                                    ui.label(format!(
+                                        "{} {} ({}x: {})",
+                                        substat.key,
+                                        substat.value,
+                                        substat.tiers.len(),
+                                        tiers.join(" ")
+                                    ));
+                                }
+                            });
+                            ui.label(format!("{:.1}", analysis.crit_value));
+                            ui.label(format!("{:.1}", analysis.score));
+                            ui.end_row();
+                        }
+                    });
+            });
+        if sort != self.artifact_sort {
+            self.artifact_sort = sort;
+            artifacts::sort(&mut self.artifact_analysis, sort.0, sort.1);
+        }
+
+        ui.separator();
+        egui::Sides::new().show(
+            ui,
+            |ui| {
+                ui.label(format!(
+                    "Scored by {}.  Roll tiers run from 1 (70%) to 4 (max roll).",
+                    self.saved_state.score_settings.method
+                ));
+            },
+            |ui| {
+                if ui.button("Ok").clicked() {
+                    ui.close()
+                }
+            },
+        );
+    }
+
+    fn artifact_cleanup_modal(&mut self, ui: &mut egui::Ui) {
+        if let Some(rx) = self.artifact_cleanup_rx.take()
+            && let Some(recommendations) = rx.blocking_recv().toast_error(self)
+        {
+            self.artifact_cleanup = recommendations;
+        }
+
+        ui.set_width(750.0);
+        ui.heading("Artifact Cleanup");
+        ui.separator();
+
+        let settings = &mut self.saved_state.score_settings;
+        let keep_score_changed = ui
+            .horizontal(|ui| {
+                ui.label(format!(
+                    "Lock artifacts expected to reach a {} of",
+                    settings.method
+                ));
+                ui.add(DragValue::new(&mut settings.keep_score).range(0.0..=100.0))
+                    .changed()
+            })
+            .inner;
+        if keep_score_changed {
+            self.artifacts_request_cleanup();
+        }
+
+        egui::ScrollArea::vertical()
+            .max_height(300.)
+            .show(ui, |ui| {
+                egui::Grid::new("artifact_cleanup")
+                    .striped(true)
+                    .num_columns(7)
+                    .show(ui, |ui| {
+                        for name in [
+                            "Action",
+                            "Set",
+                            "Slot",
+                            "Main stat",
+                            "Level",
+                            "Location",
+                            "Reason",
+                        ] {
+                            ui.label(name);
+                        }
+                        ui.end_row();
+
+                        for recommendation in &self.artifact_cleanup {
+                            let artifact = &recommendation.artifact;
+                            ui.label(match recommendation.action {
+                                CleanupAction::Lock => {
+                                    format!("{} Lock", egui_material_icons::icons::ICON_LOCK)
+                                }
+                                CleanupAction::Unlock => {
+                                    format!("{} Unlock", egui_material_icons::icons::ICON_LOCK_OPEN)
+                                }
+                            });
+                            ui.label(&artifact.set_key);
+                            ui.label(&artifact.slot_key);
+                            ui.label(&artifact.main_stat_key);
+                            ui.label(format!("+{}", artifact.level));
+                            ui.label(&recommendation.location);
+                            ui.label(&recommendation.reason);
+                            ui.end_row();
+                        }
+                    });
+            });
+
+        ui.separator();
+        let locks = self
+            .artifact_cleanup
+            .iter()
+            .filter(|recommendation| recommendation.action == CleanupAction::Lock)
+            .count();
+        let unlocks = self.artifact_cleanup.len() - locks;
+        egui::Sides::new().show(
+            ui,
+            |ui| {
+                ui.label(format!("{locks} to lock, {unlocks} to unlock"));
+            },
+            |ui| {
+                if ui.button("Ok").clicked() {
+                    ui.close()
+                }
+                if ui
+                    .add_enabled(!self.artifact_cleanup.is_empty(), Button::new("Copy JSON"))
+                    .clicked()
+                    && let Some(json) =
+                        serde_json::to_string(&self.artifact_cleanup).toast_error(self)
+                {
+                    ui.ctx().copy_text(json);
+                    self.toasts.info("Cleanup list copied to clipboard");
+                }
+            },
+        );
+    }
+
+    fn artifact_settings_modal(&mut self, ui: &mut egui::Ui) {
+        self.artifacts_handle_analysis();
+
+        ui.set_width(300.0);
+        ui.heading("Artifact Scoring Settings");
+        ui.separator();
+
+        let prev_settings = self.saved_state.score_settings.clone();
+        let settings = &mut self.saved_state.score_settings;
+        egui::ComboBox::from_label("Score")
+            .selected_text(settings.method.to_string())
+            .show_ui(ui, |ui| {
+                for method in [ScoreMethod::CritValue, ScoreMethod::Weighted] {
+                    ui.selectable_value(&mut settings.method, method, method.to_string());
+                }
+            });
+        if settings.method != prev_settings.method {
+            settings.keep_score = settings.method.default_keep_score();
+        }
+
+        let mut characters: Vec<String> = self
+            .artifact_analysis
+            .iter()
+            .map(|analysis| analysis.artifact.location.clone())
+            .chain(settings.character_weights.keys().cloned())
+            .filter(|character| !character.is_empty())
+            .collect();
+        characters.sort_unstable();
+        characters.dedup();
+
+        let character = &mut self.artifact_weights_character;
+        ui.add_enabled_ui(settings.method == ScoreMethod::Weighted, |ui| {
+            egui::ComboBox::from_label("Build")
+                .selected_text(if character.is_empty() {
+                    "Default"
+                } else {
+                    character.as_str()
+                })
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(character, String::new(), "Default");
+                    for name in characters {
+                        ui.selectable_value(character, name.clone(), name);
+                    }
+                });
+
+            let mut weights = settings.weights_for(character).clone();
+            egui::Grid::new("score_weights")
+                .striped(true)
+                .show(ui, |ui| {
+                    for (key, _) in MAX_ROLLS {
+                        let weight = weights.entry(key.to_string()).or_default();
+                        ui.label(key);
+                        ui.add(DragValue::new(weight).range(0.0..=1.0).speed(0.05));
+                        ui.end_row();
+                    }
+                });
+            weights.retain(|_, weight| *weight > 0.);
+
+            if character.is_empty() {
+                settings.weights = weights;
+            } else if settings.character_weights.contains_key(character.as_str()) {
+                if ui.button("Use default weights").clicked() {
+                    settings.character_weights.remove(character.as_str());
+                } else {
+                    settings
+                        .character_weights
+                        .insert(character.clone(), weights);
+                }
+            } else if &weights != settings.weights_for(character) {
+                settings
+                    .character_weights
+                    .insert(character.clone(), weights);
+            }
+        });
+
+        if prev_settings != self.saved_state.score_settings {
+            self.artifacts_request_analysis();
+        }
+
+        ui.separator();
+        egui::Sides::new().show(
+            ui,
+            |_ui| {},
+            |ui| {
+                if ui.button("Ok").clicked() {
+                    ui.close()
+                }
+            },
+        );
+    }
+
     fn wish_ui(&mut self, ui: &mut egui::Ui) {
         self.optimizer_handle_export(ui).toast_error(self);
 
@@ -717,6 +1650,9 @@ impl IrminsulApp {
                 .set_filter(self.saved_state.tracing_level.get_filter());
         }
         ui.end_row();
+        self.game_data_ui(ui);
+        ui.separator();
+        self.update_settings_ui(ui);
         ui.separator();
         egui::Sides::new().show(
             ui,
@@ -729,6 +1665,86 @@ impl IrminsulApp {
         );
     }
 
+    fn update_settings_ui(&mut self, ui: &mut egui::Ui) {
+        let update_settings = &mut self.saved_state.update_settings;
+        let prev_channel = update_settings.channel;
+        egui::ComboBox::from_label("Update channel")
+            .selected_text(update_settings.channel.to_string())
+            .show_ui(ui, |ui| {
+                for channel in [UpdateChannel::Stable, UpdateChannel::Prerelease] {
+                    ui.selectable_value(&mut update_settings.channel, channel, channel.to_string());
+                }
+            });
+        if prev_channel != update_settings.channel {
+            self.toasts
+                .info("Updates on the new channel are checked for on the next start");
+        }
+        let update_settings = &mut self.saved_state.update_settings;
+        if let Some(version) = update_settings.skipped_version.clone() {
+            ui.horizontal(|ui| {
+                ui.label(format!("Skipping updates up to {version}"));
+                if ui.button("Clear").clicked() {
+                    update_settings.skipped_version = None;
+                }
+            });
+        }
+    }
+
+    fn game_data_ui(&mut self, ui: &mut egui::Ui) {
+        if let Some(game_data_dialog) = &mut self.game_data_dialog
+            && let Some(path) = game_data_dialog.take_picked()
+        {
+            // Check the file loads before saving it so a bad file doesn't
+            // fall back to the built-in data on the next start.  Loading takes
+            // a while, so it's done off the UI thread.
+            let (tx, rx) = oneshot::channel();
+            thread::spawn(move || {
+                let result = monitor::load_game_data(&path).map(drop);
+                let _ = tx.send((path, result));
+            });
+            self.game_data_check_rx = Some(rx);
+        }
+        if let Some(rx) = &mut self.game_data_check_rx {
+            match rx.try_recv() {
+                Ok((path, result)) => {
+                    self.game_data_check_rx = None;
+                    if result.toast_error(self).is_some() {
+                        self.saved_state.game_data_path = Some(path);
+                        self.toasts
+                            .info("Restart Irminsul to load the new game data");
+                    }
+                }
+                Err(oneshot::error::TryRecvError::Empty) => (),
+                Err(oneshot::error::TryRecvError::Closed) => self.game_data_check_rx = None,
+            }
+        }
+
+        ui.label("Game data");
+        if let Some(path) = &self.game_data_arg {
+            ui.label(format!("{} (set with --game-data)", path.display()));
+            return;
+        }
+        ui.horizontal(|ui| {
+            match &self.saved_state.game_data_path {
+                Some(path) => ui.label(path.display().to_string()),
+                None => ui.label("Built-in"),
+            };
+            if self.game_data_check_rx.is_some() {
+                ui.spinner();
+            } else if ui.button("Choose...").clicked() {
+                let mut game_data_dialog =
+                    FileDialog::new().add_file_filter_extensions("Game data", vec!["json", "gz"]);
+                game_data_dialog.pick_file();
+                self.game_data_dialog = Some(game_data_dialog);
+            }
+            if self.saved_state.game_data_path.is_some() && ui.button("Clear").clicked() {
+                self.saved_state.game_data_path = None;
+                self.toasts
+                    .info("Restart Irminsul to load the built-in game data");
+            }
+        });
+    }
+
     fn bug_report_modal(&mut self, ui: &mut egui::Ui) {
         ui.set_width(300.0);
         ui.heading("Bug Report");
@@ -740,6 +1756,40 @@ impl IrminsulApp {
             });
         }
         ui.separator();
+        ui.label("Diagnostics");
+        let session_keys = self.state_rx.borrow().session_keys.clone();
+        if session_keys.is_empty() {
+            ui.weak("No game sessions seen yet");
+        }
+        egui::Grid::new("session_keys")
+            .striped(true)
+            .show(ui, |ui| {
+                for (i, session_key) in session_keys.iter().enumerate() {
+                    ui.label(format!("Session {}", i + 1));
+                    ui.label(session_key.to_string());
+                    ui.end_row();
+                    ui.label(
+                        DateTime::<Local>::from(session_key.started)
+                            .format("%H:%M:%S")
+                            .to_string(),
+                    );
+                    ui.weak(format!(
+                        "{} ↔ {}{}",
+                        session_key.client,
+                        session_key.server,
+                        session_key
+                            .interface
+                            .as_deref()
+                            .map(|interface| format!(" on {interface}"))
+                            .unwrap_or_default()
+                    ));
+                    ui.end_row();
+                }
+            });
+        if let Some(path) = keys::storage_keys_path() {
+            ui.weak(format!("Extra keys are loaded from {}", path.display()));
+        }
+        ui.separator();
         egui::Sides::new().show(
             ui,
             |_ui| {},
@@ -757,15 +1807,105 @@ impl IrminsulApp {
         );
     }
 
+    /// Capture settings in effect, with command line options taking
+    /// precedence over the saved ones.
+    fn capture_settings(&self) -> CaptureSettings {
+        self.capture_overrides
+            .apply(&self.saved_state.capture_settings)
+    }
+
+    fn start_capture(&mut self) {
+        let settings = self.capture_settings();
+        if settings
+            .validate(self.capture_backend)
+            .context("Capture not started")
+            .toast_error(self)
+            .is_none()
+        {
+            return;
+        }
+        if let Err(e) = self.ui_message_tx.send(Message::StartCapture(settings)) {
+            tracing::error!("Failed to send start capture message: {e}");
+        }
+    }
+
     fn capture_settings_modal(&mut self, ui: &mut egui::Ui) {
         ui.set_width(300.0);
-        ui.heading("Genshin Optimizer Settings");
+        ui.heading("Capture Settings");
         ui.separator();
         ui.checkbox(
             &mut self.saved_state.auto_start_capture,
             "Start capture on Irminsul launch",
         );
         ui.separator();
+        egui::Grid::new("capture_filter_settings")
+            .num_columns(2)
+            .show(ui, |ui| {
+                ui.label("Ports");
+                if let Some(ports) = &self.capture_overrides.ports {
+                    ui.add_enabled_ui(false, |ui| ui.label(ports.to_string()))
+                        .response
+                        .on_disabled_hover_text("Set by --ports");
+                } else if ui
+                    .text_edit_singleline(&mut self.capture_ports_text)
+                    .changed()
+                    && let Ok(ports) = self.capture_ports_text.parse()
+                {
+                    self.saved_state.capture_settings.ports = ports;
+                }
+                ui.end_row();
+
+                ui.label("Filter");
+                if let Some(filter) = &self.capture_overrides.filter {
+                    ui.add_enabled_ui(false, |ui| ui.label(filter))
+                        .response
+                        .on_disabled_hover_text("Set by --capture-filter");
+                } else if ui
+                    .add(
+                        egui::TextEdit::singleline(&mut self.capture_filter_text)
+                            .hint_text("Built from ports"),
+                    )
+                    .on_hover_text("BPF expression used by the pcap backend")
+                    .changed()
+                {
+                    let filter = self.capture_filter_text.trim();
+                    self.saved_state.capture_settings.filter =
+                        (!filter.is_empty()).then(|| filter.to_string());
+                }
+                ui.end_row();
+            });
+        if self.capture_overrides.ports.is_none()
+            && let Err(e) = self.capture_ports_text.parse::<PortSet>()
+        {
+            ui.colored_label(ui.visuals().error_fg_color, e.to_string());
+        } else if let Err(e) = self.capture_settings().validate(self.capture_backend) {
+            ui.colored_label(ui.visuals().error_fg_color, e.to_string());
+        }
+        if let Some(fallback) = self.capture_backend.fallback() {
+            ui.checkbox(
+                &mut self.saved_state.capture_settings.fallback,
+                format!(
+                    "Capture with {fallback:?} when {:?} fails",
+                    self.capture_backend
+                ),
+            );
+        }
+        if self.capture_backend == capture::BackendType::Pcap {
+            ui.separator();
+            self.capture_interfaces_ui(ui);
+        }
+        if self.capture_backend == capture::BackendType::Pipe {
+            ui.separator();
+            let pipe_path = self.capture_settings().pipe_path();
+            ui.label("Send a pcap stream of the game's traffic to:");
+            ui.horizontal(|ui| {
+                ui.label(&pipe_path);
+                if ui.button("Copy").clicked() {
+                    ui.ctx().copy_text(pipe_path.clone());
+                }
+            });
+        }
+        ui.separator();
         egui::Sides::new().show(
             ui,
             |_ui| {},
@@ -777,9 +1917,91 @@ impl IrminsulApp {
         );
     }
 
+    fn capture_interfaces_ui(&mut self, ui: &mut egui::Ui) {
+        egui::Sides::new().show(
+            ui,
+            |ui| {
+                ui.label("Interfaces");
+            },
+            |ui| {
+                if self.capture_overrides.interfaces.is_empty()
+                    && ui
+                        .button(egui_material_icons::icons::ICON_REFRESH)
+                        .on_hover_text("Refresh")
+                        .clicked()
+                {
+                    self.capture_interfaces = None;
+                }
+            },
+        );
+        if !self.capture_overrides.interfaces.is_empty() {
+            ui.weak(format!(
+                "Set by --interface: {}",
+                self.capture_overrides.interfaces.join(", ")
+            ));
+            return;
+        }
+
+        let interfaces = self
+            .capture_interfaces
+            .get_or_insert_with(|| capture::list_interfaces().map_err(|e| e.to_string()))
+            .clone();
+        let interfaces = match interfaces {
+            Ok(interfaces) => interfaces,
+            Err(e) => {
+                ui.colored_label(
+                    ui.visuals().error_fg_color,
+                    format!("Unable to list interfaces: {e}"),
+                );
+                return;
+            }
+        };
+
+        // Interfaces picked earlier that have since gone away are still
+        // listed so they can be unpicked.
+        let picked = &mut self.saved_state.capture_settings.interfaces;
+        let missing = picked
+            .iter()
+            .filter(|name| !interfaces.iter().any(|interface| interface.name == **name))
+            .map(|name| Interface {
+                name: name.clone(),
+                description: Some("Not found".to_string()),
+            })
+            .collect::<Vec<_>>();
+        egui::ScrollArea::vertical()
+            .max_height(150.)
+            .show(ui, |ui| {
+                for interface in interfaces.iter().chain(&missing) {
+                    let mut checked = picked.contains(&interface.name);
+                    if ui.checkbox(&mut checked, interface.to_string()).changed() {
+                        if checked {
+                            picked.push(interface.name.clone());
+                        } else {
+                            picked.retain(|name| *name != interface.name);
+                        }
+                    }
+                }
+            });
+        if picked.is_empty() {
+            ui.weak("None picked, capturing on every connected interface");
+        }
+    }
+
     fn optimizer_settings_modal(&mut self, ui: &mut egui::Ui) {
         ui.set_width(300.0);
-        ui.heading("Genshin Optimizer Settings");
+        ui.heading("Export Settings");
+        ui.separator();
+        egui::ComboBox::from_label("Format")
+            .selected_text(self.saved_state.export_format.to_string())
+            .show_ui(ui, |ui| {
+                for format in ExportFormat::ALL {
+                    ui.selectable_value(
+                        &mut self.saved_state.export_format,
+                        format,
+                        format.to_string(),
+                    );
+                }
+            });
         ui.separator();
         ui.checkbox(
             &mut self.saved_state.export_settings.include_characters,
@@ -902,28 +2124,38 @@ impl IrminsulApp {
             return Ok(());
         };
 
-        let json = rx.blocking_recv()??;
+        let export = rx.blocking_recv()??;
+        let target = std::mem::replace(
+            &mut self.optimizer_export_target,
+            OptimizerExportTarget::None,
+        );
 
-        match self.optimizer_export_target {
-            OptimizerExportTarget::None => {
-                tracing::warn!("Unexpected json export");
+        match (target, export) {
+            (OptimizerExportTarget::None, _) => {
+                tracing::warn!("Unexpected export");
             }
-            OptimizerExportTarget::Clipboard => {
+            (OptimizerExportTarget::Clipboard, Export::Text(json)) => {
                 self.optimizer_save_to_clipboard(ui, json)?;
             }
-            OptimizerExportTarget::File => {
+            (OptimizerExportTarget::Clipboard, Export::Sheets(_)) => {
+                bail!("Spreadsheets can't be copied to the clipboard");
+            }
+            (OptimizerExportTarget::File, Export::Text(json)) => {
                 self.optimizer_save_to_file(json)?;
             }
+            (OptimizerExportTarget::File, Export::Sheets(sheets)) => {
+                self.spreadsheet_save_to_file(&sheets)?;
+            }
         }
-
-        self.optimizer_export_target = OptimizerExportTarget::None;
         Ok(())
     }
 
     fn optimizer_save_to_clipboard(&mut self, ui: &mut egui::Ui, json: String) -> Result<()> {
         ui.ctx().copy_text(json);
-        self.toasts
-            .info("Genshin Optimizer data copied to clipboard");
+        self.toasts.info(format!(
+            "{} data copied to clipboard",
+            self.saved_state.export_format
+        ));
         Ok(())
     }
 
@@ -937,7 +2169,31 @@ impl IrminsulApp {
         let mut writer = BufWriter::new(file);
         writer.write_all(json.as_bytes())?;
 
-        self.toasts.info("Genshin Optimizer data saved to file");
+        self.toasts.info(format!(
+            "{} data saved to file",
+            self.saved_state.export_format
+        ));
+        Ok(())
+    }
+
+    fn spreadsheet_save_to_file(&mut self, sheets: &[Sheet]) -> Result<()> {
+        let path = self
+            .optimizer_save_path
+            .take()
+            .ok_or_else(|| anyhow!("No save file path set"))?;
+
+        if path.extension().is_some_and(|ext| ext == "csv") {
+            let paths = spreadsheet::write_csv(sheets, &path)
+                .with_context(|| format!("Unable to write CSV files next to {path:?}"))?;
+            self.toasts.info(format!(
+                "Spreadsheet data saved to {} CSV files",
+                paths.len()
+            ));
+        } else {
+            spreadsheet::write_xlsx(sheets, &path)
+                .with_context(|| format!("Unable to write workbook {path:?}"))?;
+            self.toasts.info("Spreadsheet data saved to file");
+        }
         Ok(())
     }
 
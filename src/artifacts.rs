@@ -0,0 +1,560 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+use crate::good;
+use crate::player_data::RolledArtifact;
+
+/// The largest possible roll of each substat on a 5* artifact, by GOOD key.
+pub const MAX_ROLLS: [(&str, f32); 10] = [
+    ("hp", 298.75),
+    ("atk", 19.45),
+    ("def", 23.15),
+    ("hp_", 5.83),
+    ("atk_", 5.83),
+    ("def_", 7.29),
+    ("eleMas", 23.31),
+    ("enerRech_", 6.48),
+    ("critRate_", 3.89),
+    ("critDMG_", 7.77),
+];
+
+/// The largest possible roll of `key` on an artifact of `rarity`.
+///
+/// Rolls on lower rarity artifacts are 20% smaller for every star below 5.
+fn max_roll(key: &str, rarity: u32) -> Option<f32> {
+    let (_, max) = MAX_ROLLS.iter().find(|(max_key, _)| *max_key == key)?;
+    Some(max * rarity.clamp(1, 5) as f32 / 5.)
+}
+
+/// The average roll, as a fraction of the largest possible roll, over the
+/// four roll tiers.
+const AVERAGE_ROLL: f32 = 0.85;
+
+/// The tier of a roll, from 1 for a roll of 70% of the max to 4 for a max
+/// roll.
+fn roll_tier(roll: f32, max: f32) -> u32 {
+    ((roll / max * 10.).round() as i32 - 6).clamp(1, 4) as u32
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub enum ScoreMethod {
+    /// Twice the crit rate plus the crit damage.
+    #[default]
+    CritValue,
+    /// The sum of each substat's value, in max rolls, times its weight.
+    Weighted,
+}
+
+impl Display for ScoreMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScoreMethod::CritValue => write!(f, "Crit Value"),
+            ScoreMethod::Weighted => write!(f, "Weighted Rolls"),
+        }
+    }
+}
+
+impl ScoreMethod {
+    /// The score an unlocked artifact needs to be expected to reach to be
+    /// worth locking.
+    pub fn default_keep_score(&self) -> f32 {
+        match self {
+            ScoreMethod::CritValue => 30.,
+            ScoreMethod::Weighted => 5.,
+        }
+    }
+}
+
+fn default_keep_score() -> f32 {
+    ScoreMethod::default().default_keep_score()
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ScoreSettings {
+    pub method: ScoreMethod,
+    /// Substat weights for artifacts not on a character with their own.
+    pub weights: BTreeMap<String, f32>,
+    /// Substat weights for each character's build, by GOOD character key.
+    #[serde(default)]
+    pub character_weights: BTreeMap<String, BTreeMap<String, f32>>,
+    /// The score an unlocked artifact needs to be expected to reach to be
+    /// worth locking.
+    #[serde(default = "default_keep_score")]
+    pub keep_score: f32,
+}
+
+impl Default for ScoreSettings {
+    fn default() -> Self {
+        Self {
+            method: ScoreMethod::default(),
+            weights: BTreeMap::from([
+                ("critRate_".to_string(), 1.),
+                ("critDMG_".to_string(), 1.),
+                ("atk_".to_string(), 0.75),
+                ("enerRech_".to_string(), 0.5),
+                ("eleMas".to_string(), 0.5),
+            ]),
+            character_weights: BTreeMap::new(),
+            keep_score: default_keep_score(),
+        }
+    }
+}
+
+impl ScoreSettings {
+    /// The weights used to score an artifact equipped at `location`.
+    pub fn weights_for(&self, location: &str) -> &BTreeMap<String, f32> {
+        self.character_weights
+            .get(location)
+            .unwrap_or(&self.weights)
+    }
+
+    /// The substats that count towards the score of an artifact equipped at
+    /// `location`.
+    fn scored_keys(&self, location: &str) -> Vec<&str> {
+        match self.method {
+            ScoreMethod::CritValue => vec!["critRate_", "critDMG_"],
+            ScoreMethod::Weighted => self
+                .weights_for(location)
+                .iter()
+                .filter(|(_, weight)| **weight > 0.)
+                .map(|(key, _)| key.as_str())
+                .collect(),
+        }
+    }
+
+    /// Score an artifact equipped at `location` with the substat values given
+    /// by `value`.
+    fn score(&self, location: &str, value: impl Fn(&str) -> f32) -> f32 {
+        match self.method {
+            ScoreMethod::CritValue => 2. * value("critRate_") + value("critDMG_"),
+            ScoreMethod::Weighted => self
+                .weights_for(location)
+                .iter()
+                .filter_map(|(key, weight)| Some(weight * value(key) / max_roll(key, 5)?))
+                .sum(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SubstatAnalysis {
+    pub key: String,
+    pub value: f32,
+    /// The tier of every roll into this substat, starting with its initial
+    /// value.
+    pub tiers: Vec<u32>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ArtifactAnalysis {
+    pub guid: u64,
+    pub artifact: good::Artifact,
+    pub substats: Vec<SubstatAnalysis>,
+    pub crit_value: f32,
+    pub score: f32,
+}
+
+pub fn analyze(artifacts: &[RolledArtifact], settings: &ScoreSettings) -> Vec<ArtifactAnalysis> {
+    artifacts
+        .iter()
+        .map(|rolled| {
+            let artifact = &rolled.artifact;
+            let substats: Vec<SubstatAnalysis> = artifact
+                .substats
+                .iter()
+                .zip(&rolled.rolls)
+                .map(|(substat, rolls)| {
+                    let tiers = match max_roll(&substat.key, artifact.rarity) {
+                        Some(max) => rolls.iter().map(|roll| roll_tier(*roll, max)).collect(),
+                        None => Vec::new(),
+                    };
+                    SubstatAnalysis {
+                        key: substat.key.clone(),
+                        value: substat.value,
+                        tiers,
+                    }
+                })
+                .collect();
+
+            let value = |key: &str| {
+                substats
+                    .iter()
+                    .find(|substat| substat.key == key)
+                    .map_or(0., |substat| substat.value)
+            };
+            let crit_value = 2. * value("critRate_") + value("critDMG_");
+            let score = settings.score(&artifact.location, value);
+
+            ArtifactAnalysis {
+                guid: rolled.guid,
+                artifact: artifact.clone(),
+                substats,
+                crit_value,
+                score,
+            }
+        })
+        .collect()
+}
+
+/// The max level of an artifact of `rarity`.  Every fourth level adds a roll.
+fn max_level(rarity: u32) -> u32 {
+    match rarity {
+        5 => 20,
+        4 => 16,
+        3 => 12,
+        _ => 4,
+    }
+}
+
+/// How far `artifact` could grow with its remaining rolls.
+struct Potential {
+    /// The most each substat could grow to if every remaining roll went its
+    /// way.
+    values: BTreeMap<&'static str, f32>,
+    /// The score `artifact` is expected to reach at max level, with every
+    /// remaining roll an average roll into a random line.
+    expected_score: f32,
+}
+
+fn potential(artifact: &good::Artifact, settings: &ScoreSettings) -> Potential {
+    let mut rolls = (max_level(artifact.rarity) / 4).saturating_sub(artifact.level / 4);
+    let mut lines: Vec<(&str, f32)> = artifact
+        .substats
+        .iter()
+        .map(|substat| (substat.key.as_str(), substat.value))
+        .collect();
+
+    // The first upgrade spends its roll on activating the unactivated line.
+    if rolls > 0
+        && let Some(substat) = artifact.unactivated_substats.first()
+    {
+        lines.push((substat.key.as_str(), substat.value));
+        rolls -= 1;
+    }
+
+    // With fewer than four lines, the next roll adds a line that could be
+    // any substat.
+    let has_open_line = lines.len() < 4;
+    let line_value = |key: &str| lines.iter().find(|(line, _)| *line == key).map(|(_, v)| *v);
+
+    let mut values = BTreeMap::new();
+    let mut line_roll_scores = 0.;
+    let mut open_line_roll_scores = Vec::new();
+    for (key, _) in MAX_ROLLS {
+        let Some(max) = max_roll(key, artifact.rarity) else {
+            continue;
+        };
+        let value = match line_value(key) {
+            Some(value) => value + rolls as f32 * max,
+            None if has_open_line => rolls as f32 * max,
+            None => continue,
+        };
+        values.insert(key, value);
+
+        let roll_score = settings.score(&artifact.location, |k| if k == key { max } else { 0. });
+        if line_value(key).is_some() {
+            line_roll_scores += AVERAGE_ROLL * roll_score;
+        } else if key != artifact.main_stat_key {
+            open_line_roll_scores.push(AVERAGE_ROLL * roll_score);
+        }
+    }
+
+    let score = settings.score(&artifact.location, |key| line_value(key).unwrap_or(0.));
+
+    // The open line, if any, is filled by the next roll with any substat
+    // the artifact doesn't already have, and then rolls into like the rest.
+    let mut expected_score = score;
+    let mut expected_rolls = rolls;
+    let mut line_count = lines.len();
+    if has_open_line && expected_rolls > 0 && !open_line_roll_scores.is_empty() {
+        let open_line_roll_score =
+            open_line_roll_scores.iter().sum::<f32>() / open_line_roll_scores.len() as f32;
+        expected_score += open_line_roll_score;
+        line_roll_scores += open_line_roll_score;
+        expected_rolls -= 1;
+        line_count += 1;
+    }
+    if line_count > 0 {
+        expected_score += expected_rolls as f32 * line_roll_scores / line_count as f32;
+    }
+
+    Potential {
+        values,
+        expected_score,
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+pub enum CleanupAction {
+    Lock,
+    Unlock,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct CleanupRecommendation {
+    pub guid: u64,
+    pub location: String,
+    pub action: CleanupAction,
+    pub reason: String,
+    pub artifact: good::Artifact,
+}
+
+/// Recommend locking unlocked artifacts that are expected to reach the keep
+/// score, and unlocking locked artifacts that can never catch up with
+/// another artifact of the same set, slot, main stat and rarity.
+///
+/// An artifact is dominated when, on every substat that counts towards its
+/// score, even its best possible value is no higher than the other
+/// artifact's current value.
+pub fn recommend_cleanup(
+    artifacts: &[RolledArtifact],
+    settings: &ScoreSettings,
+) -> Vec<CleanupRecommendation> {
+    let mut groups: HashMap<(&str, &str, &str, u32), Vec<&good::Artifact>> = HashMap::new();
+    for rolled in artifacts {
+        let artifact = &rolled.artifact;
+        groups
+            .entry((
+                &artifact.set_key,
+                &artifact.slot_key,
+                &artifact.main_stat_key,
+                artifact.rarity,
+            ))
+            .or_default()
+            .push(artifact);
+    }
+
+    let value = |artifact: &good::Artifact, key: &str| {
+        artifact
+            .substats
+            .iter()
+            .find(|substat| substat.key == key)
+            .map_or(0., |substat| substat.value)
+    };
+
+    artifacts
+        .iter()
+        .filter_map(|rolled| {
+            let artifact = &rolled.artifact;
+            let potential = potential(artifact, settings);
+            let keys = settings.scored_keys(&artifact.location);
+            let group = &groups[&(
+                artifact.set_key.as_str(),
+                artifact.slot_key.as_str(),
+                artifact.main_stat_key.as_str(),
+                artifact.rarity,
+            )];
+
+            let dominated_by = group.iter().find(|other| {
+                let best = |key: &str| potential.values.get(key).copied().unwrap_or(0.);
+                !std::ptr::eq(**other, artifact)
+                    && !keys.is_empty()
+                    && keys.iter().all(|key| best(key) <= value(other, key))
+                    && keys.iter().any(|key| best(key) < value(other, key))
+            });
+
+            let (action, reason) = match (artifact.lock, dominated_by) {
+                (true, Some(other)) => {
+                    let location = match other.location.as_str() {
+                        "" => "in the inventory",
+                        location => &format!("on {location}"),
+                    };
+                    (
+                        CleanupAction::Unlock,
+                        format!("Can't catch up with the +{} {location}", other.level),
+                    )
+                }
+                (false, None) if potential.expected_score >= settings.keep_score => (
+                    CleanupAction::Lock,
+                    format!(
+                        "Expected to reach a score of {:.1}",
+                        potential.expected_score
+                    ),
+                ),
+                _ => return None,
+            };
+
+            Some(CleanupRecommendation {
+                guid: rolled.guid,
+                location: artifact.location.clone(),
+                action,
+                reason,
+                artifact: artifact.clone(),
+            })
+        })
+        .collect()
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SortKey {
+    Set,
+    Slot,
+    Level,
+    Location,
+    CritValue,
+    Score,
+}
+
+pub fn sort(analyses: &mut [ArtifactAnalysis], key: SortKey, descending: bool) {
+    analyses.sort_by(|a, b| {
+        let ordering = match key {
+            SortKey::Set => a.artifact.set_key.cmp(&b.artifact.set_key),
+            SortKey::Slot => a.artifact.slot_key.cmp(&b.artifact.slot_key),
+            SortKey::Level => a.artifact.level.cmp(&b.artifact.level),
+            SortKey::Location => a.artifact.location.cmp(&b.artifact.location),
+            SortKey::CritValue => a.crit_value.total_cmp(&b.crit_value),
+            SortKey::Score => a.score.total_cmp(&b.score),
+        };
+        if descending {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rolled(location: &str, substats: &[(&str, &[f32])]) -> RolledArtifact {
+        RolledArtifact {
+            guid: 1,
+            artifact: good::Artifact {
+                set_key: "GladiatorsFinale".to_string(),
+                slot_key: "flower".to_string(),
+                level: 20,
+                rarity: 5,
+                main_stat_key: "hp".to_string(),
+                location: location.to_string(),
+                lock: false,
+                substats: substats
+                    .iter()
+                    .map(|(key, rolls)| good::Substat {
+                        key: key.to_string(),
+                        value: rolls.iter().sum(),
+                        initial_value: rolls[0],
+                    })
+                    .collect(),
+                total_rolls: 0,
+                astral_mark: false,
+                elixer_crafted: false,
+                unactivated_substats: Vec::new(),
+            },
+            rolls: substats.iter().map(|(_, rolls)| rolls.to_vec()).collect(),
+        }
+    }
+
+    #[test]
+    fn rolls_are_sorted_into_tiers() {
+        let artifact = rolled("", &[("critRate_", &[2.72, 3.11, 3.5, 3.89])]);
+        let analysis = analyze(&[artifact], &ScoreSettings::default());
+
+        assert_eq!(analysis[0].substats[0].tiers, [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn crit_value_counts_crit_rate_twice() {
+        let artifact = rolled("", &[("critRate_", &[3.89]), ("critDMG_", &[7.77])]);
+        let analysis = analyze(&[artifact], &ScoreSettings::default());
+
+        assert!((analysis[0].crit_value - 15.55).abs() < 0.001);
+        assert_eq!(analysis[0].score, analysis[0].crit_value);
+    }
+
+    #[test]
+    fn a_characters_weights_override_the_defaults() {
+        let settings = ScoreSettings {
+            method: ScoreMethod::Weighted,
+            weights: BTreeMap::from([("atk_".to_string(), 1.)]),
+            character_weights: BTreeMap::from([(
+                "Nahida".to_string(),
+                BTreeMap::from([("eleMas".to_string(), 1.)]),
+            )]),
+            ..Default::default()
+        };
+        let substats: &[(&str, &[f32])] = &[("atk_", &[5.83, 5.83]), ("eleMas", &[23.31])];
+        let analysis = analyze(
+            &[rolled("", substats), rolled("Nahida", substats)],
+            &settings,
+        );
+
+        assert!((analysis[0].score - 2.).abs() < 0.001);
+        assert!((analysis[1].score - 1.).abs() < 0.001);
+    }
+
+    #[test]
+    fn locked_artifacts_that_cant_catch_up_are_unlocked() {
+        let mut best = rolled(
+            "Nahida",
+            &[("critRate_", &[3.89; 5]), ("critDMG_", &[7.77])],
+        );
+        best.guid = 1;
+        let mut worse = rolled("", &[("critRate_", &[2.72]), ("critDMG_", &[5.44])]);
+        worse.guid = 2;
+        worse.artifact.lock = true;
+
+        let recommendations = recommend_cleanup(&[best, worse], &ScoreSettings::default());
+
+        assert_eq!(recommendations.len(), 2);
+        assert_eq!(recommendations[0].guid, 1);
+        assert_eq!(recommendations[0].action, CleanupAction::Lock);
+        assert_eq!(recommendations[1].guid, 2);
+        assert_eq!(recommendations[1].action, CleanupAction::Unlock);
+    }
+
+    #[test]
+    fn low_level_artifacts_are_judged_on_their_potential() {
+        let substats: &[(&str, &[f32])] = &[
+            ("critRate_", &[3.89]),
+            ("critDMG_", &[7.77]),
+            ("atk_", &[5.83]),
+        ];
+        let mut artifact = rolled("", substats);
+        artifact.artifact.level = 0;
+        artifact.artifact.unactivated_substats = vec![good::Substat {
+            key: "atk".to_string(),
+            value: 19.45,
+            initial_value: 19.45,
+        }];
+
+        // Four rolls are left after the unactivated line.
+        let potential = potential(&artifact.artifact, &ScoreSettings::default());
+        assert!(!potential.values.contains_key("hp"));
+        assert!((potential.values["critDMG_"] - 5. * 7.77).abs() < 0.001);
+
+        // Each of those rolls is expected to land on a crit line half the
+        // time, at 85% of the max.
+        let expected = 15.55 + 4. * 0.85 * (7.78 + 7.77) / 4.;
+        assert!((potential.expected_score - expected).abs() < 0.001);
+    }
+
+    #[test]
+    fn artifacts_that_only_might_reach_the_keep_score_arent_locked() {
+        let mut artifact = rolled("", &[("critRate_", &[2.72]), ("critDMG_", &[5.44])]);
+        artifact.artifact.level = 0;
+
+        // Max rolls into crit could reach a crit value of 49.8, but an
+        // average upgrade falls short of 30.
+        let potential = potential(&artifact.artifact, &ScoreSettings::default());
+        assert!(2. * potential.values["critRate_"] + potential.values["critDMG_"] >= 30.);
+        assert!(potential.expected_score < 30.);
+        assert!(recommend_cleanup(&[artifact], &ScoreSettings::default()).is_empty());
+    }
+
+    #[test]
+    fn artifacts_are_only_compared_with_the_same_rarity() {
+        let mut best = rolled("", &[("critRate_", &[3.89; 5]), ("critDMG_", &[7.77])]);
+        best.guid = 1;
+        best.artifact.lock = true;
+        let mut worse = rolled("", &[("critRate_", &[2.18]), ("critDMG_", &[4.35])]);
+        worse.guid = 2;
+        worse.artifact.rarity = 4;
+        worse.artifact.level = 16;
+        worse.artifact.lock = true;
+
+        assert!(recommend_cleanup(&[best, worse], &ScoreSettings::default()).is_empty());
+    }
+}
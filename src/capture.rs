@@ -1,17 +1,43 @@
+pub mod datagram;
+#[cfg(unix)]
+pub mod helper;
 #[cfg(feature = "pcap")]
 mod pcap_backend;
+mod pipe_backend;
 
 #[cfg(windows)]
 mod pktmon_backend;
 
+use std::collections::BTreeSet;
 use std::fmt::{Debug, Display};
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::SystemTime;
 
-use anyhow::Error;
+use anyhow::{Error, anyhow, bail};
 use async_trait::async_trait;
 use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
 
+use crate::capture::datagram::Datagram;
+
+/// Ports the game servers are on when none are configured.
 pub const PORT_RANGE: (u16, u16) = (22101, 22102);
 
+/// Most ports the pktmon backend captures on.  Pktmon filters match a single
+/// port each, so a wide range would add thousands of filters.
+pub const MAX_PKTMON_PORTS: usize = 64;
+
+/// Packets that can be waiting between capture and the monitor before new
+/// ones are dropped.  Game packets are at most an MTU, so this caps the
+/// backlog at a few megabytes.
+pub const PACKET_QUEUE_LEN: usize = 4096;
+
+/// Handshakes and disconnects are sent as a bare 20 byte payload rather than
+/// a KCP segment.
+const HANDSHAKE_PAYLOAD_LEN: usize = 20;
+
 #[derive(Debug)]
 #[allow(dead_code)]
 pub enum CaptureError {
@@ -41,9 +67,342 @@ impl Display for CaptureError {
 
 pub type Result<T> = std::result::Result<T, CaptureError>;
 
+/// Set of UDP ports the game servers use, written as a comma separated list
+/// of ports and inclusive ranges, e.g. `22101-22102,22201`.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct PortSet(Vec<(u16, u16)>);
+
+impl PortSet {
+    pub fn contains(&self, port: u16) -> bool {
+        self.0
+            .iter()
+            .any(|(start, end)| (*start..=*end).contains(&port))
+    }
+
+    /// Every distinct port in the set, in order.
+    #[cfg_attr(not(windows), allow(dead_code))]
+    pub fn ports(&self) -> BTreeSet<u16> {
+        self.0
+            .iter()
+            .flat_map(|(start, end)| *start..=*end)
+            .collect()
+    }
+
+    /// BPF expression matching UDP traffic on these ports.  Fragments after
+    /// the first have no UDP header to match ports against, so all of them
+    /// are let through to be reassembled.
+    pub fn bpf_filter(&self) -> String {
+        let ports = self
+            .0
+            .iter()
+            .map(|(start, end)| {
+                if start == end {
+                    format!("port {start}")
+                } else {
+                    format!("portrange {start}-{end}")
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" or ");
+        format!("(udp and ({ports} or ip[6:2] & 0x1fff != 0)) or ip6[6] == 44")
+    }
+}
+
+impl Default for PortSet {
+    fn default() -> Self {
+        Self(vec![PORT_RANGE])
+    }
+}
+
+impl Display for PortSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, (start, end)) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            if start == end {
+                write!(f, "{start}")?;
+            } else {
+                write!(f, "{start}-{end}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for PortSet {
+    type Err = Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let parse_port = |port: &str| {
+            port.trim()
+                .parse::<u16>()
+                .map_err(|e| anyhow!("Invalid port \"{}\": {e}", port.trim()))
+        };
+
+        let mut ranges = Vec::new();
+        for range in s.split(',').filter(|range| !range.trim().is_empty()) {
+            let (start, end) = match range.split_once('-') {
+                Some((start, end)) => (parse_port(start)?, parse_port(end)?),
+                None => {
+                    let port = parse_port(range)?;
+                    (port, port)
+                }
+            };
+            if start > end {
+                bail!("Port range {start}-{end} is backwards");
+            }
+            ranges.push((start, end));
+        }
+
+        if ranges.is_empty() {
+            bail!("No ports given");
+        }
+        Ok(Self(ranges))
+    }
+}
+
+impl TryFrom<String> for PortSet {
+    type Error = Error;
+
+    fn try_from(value: String) -> std::result::Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<PortSet> for String {
+    fn from(value: PortSet) -> Self {
+        value.to_string()
+    }
+}
+
+/// What the capture backends listen for.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct CaptureSettings {
+    #[serde(default)]
+    pub ports: PortSet,
+    /// BPF expression the pcap backend uses instead of one built from
+    /// `ports`.
+    #[serde(default)]
+    pub filter: Option<String>,
+    /// Names of the interfaces the pcap backend captures on, or empty for
+    /// every connected interface.
+    #[serde(default)]
+    pub interfaces: Vec<String>,
+    /// Capture with the backend's fallback when it can't be started.
+    #[serde(default = "default_fallback")]
+    pub fallback: bool,
+    /// Where the pipe backend listens instead of the default path.  Only set
+    /// from the command line.
+    #[serde(skip)]
+    pub pipe: Option<String>,
+}
+
+fn default_fallback() -> bool {
+    true
+}
+
+impl Default for CaptureSettings {
+    fn default() -> Self {
+        Self {
+            ports: PortSet::default(),
+            filter: None,
+            interfaces: Vec::new(),
+            fallback: default_fallback(),
+            pipe: None,
+        }
+    }
+}
+
+/// Capture settings given on the command line, which take precedence over
+/// the saved ones.
+#[derive(Clone, Debug, Default)]
+pub struct CaptureOverrides {
+    pub ports: Option<PortSet>,
+    pub filter: Option<String>,
+    pub interfaces: Vec<String>,
+    pub pipe: Option<String>,
+}
+
+impl CaptureOverrides {
+    pub fn apply(&self, saved: &CaptureSettings) -> CaptureSettings {
+        CaptureSettings {
+            ports: self.ports.clone().unwrap_or_else(|| saved.ports.clone()),
+            filter: self.filter.clone().or_else(|| saved.filter.clone()),
+            interfaces: if self.interfaces.is_empty() {
+                saved.interfaces.clone()
+            } else {
+                self.interfaces.clone()
+            },
+            fallback: saved.fallback,
+            pipe: self.pipe.clone(),
+        }
+    }
+}
+
+impl CaptureSettings {
+    pub fn pcap_filter(&self) -> String {
+        match &self.filter {
+            Some(filter) => filter.clone(),
+            None => self.ports.bpf_filter(),
+        }
+    }
+
+    /// Where the pipe backend listens for a pcap stream.
+    pub fn pipe_path(&self) -> String {
+        self.pipe
+            .clone()
+            .unwrap_or_else(pipe_backend::default_pipe_path)
+    }
+
+    /// Where the capture helper listens.
+    #[cfg(unix)]
+    pub fn helper_path(&self) -> String {
+        self.pipe
+            .clone()
+            .unwrap_or_else(|| helper::DEFAULT_HELPER_SOCKET.to_string())
+    }
+
+    /// Check the settings will be accepted by `backend`, so a bad filter is
+    /// reported before capture starts rather than failing on every device.
+    pub fn validate(&self, backend: BackendType) -> anyhow::Result<()> {
+        #[cfg(feature = "pcap")]
+        {
+            let capture = pcap::Capture::dead(pcap::Linktype::ETHERNET)?;
+            capture
+                .compile(&self.pcap_filter(), true)
+                .map_err(|e| anyhow!("Invalid filter \"{}\": {e}", self.pcap_filter()))?;
+        }
+        // Too many ports for pktmon only matters when there's no fallback to
+        // capture with instead.
+        let ports = self.ports.ports().len();
+        if backend == BackendType::Pktmon
+            && backend.fallback().filter(|_| self.fallback).is_none()
+            && ports > MAX_PKTMON_PORTS
+        {
+            bail!("Pktmon can capture on at most {MAX_PKTMON_PORTS} ports, not {ports}");
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Direction {
+    ClientToServer,
+    ServerToClient,
+}
+
+/// A UDP datagram captured going to or from a game server.
+#[derive(Clone, Debug)]
+pub struct Packet {
+    pub timestamp: SystemTime,
+    pub direction: Direction,
+    /// Name of the interface the packet was captured on, when the backend
+    /// knows it.
+    pub interface: Option<Arc<str>>,
+    pub datagram: Datagram,
+}
+
+impl Packet {
+    /// Work out the direction of a datagram from which end is on a game
+    /// server port, or `None` if neither is.
+    pub fn new(
+        datagram: Datagram,
+        ports: &PortSet,
+        timestamp: SystemTime,
+        interface: Option<Arc<str>>,
+    ) -> Option<Self> {
+        let direction = if ports.contains(datagram.source.port()) {
+            Direction::ServerToClient
+        } else if ports.contains(datagram.destination.port()) {
+            Direction::ClientToServer
+        } else {
+            return None;
+        };
+        Some(Self {
+            timestamp,
+            direction,
+            interface,
+            datagram,
+        })
+    }
+
+    pub fn client(&self) -> SocketAddr {
+        match self.direction {
+            Direction::ClientToServer => self.datagram.source,
+            Direction::ServerToClient => self.datagram.destination,
+        }
+    }
+
+    pub fn server(&self) -> SocketAddr {
+        match self.direction {
+            Direction::ClientToServer => self.datagram.destination,
+            Direction::ServerToClient => self.datagram.source,
+        }
+    }
+
+    /// Whether this could be a handshake or disconnect.  A session can't be
+    /// decoded without its handshake, so these wait for room in a full queue
+    /// instead of being dropped.
+    pub fn is_handshake(&self) -> bool {
+        self.datagram.payload.len() == HANDSHAKE_PAYLOAD_LEN
+    }
+}
+
 #[async_trait]
 pub trait CaptureBackend: Send {
-    async fn next_packet(&mut self) -> Result<Vec<u8>>;
+    async fn next_packet(&mut self) -> Result<Packet>;
+
+    /// Interfaces that were picked but couldn't be captured on.
+    fn interface_failures(&self) -> &[InterfaceFailure] {
+        &[]
+    }
+
+    /// Packets dropped because the backend's queue was full.
+    fn dropped_packets(&self) -> u64 {
+        0
+    }
+}
+
+/// A network interface that can be captured on.
+#[derive(Clone, Debug)]
+pub struct Interface {
+    pub name: String,
+    pub description: Option<String>,
+}
+
+impl Display for Interface {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.description {
+            Some(description) => write!(f, "{description} ({})", self.name),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct InterfaceFailure {
+    pub interface: String,
+    pub reason: String,
+}
+
+impl Display for InterfaceFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.interface, self.reason)
+    }
+}
+
+/// List the interfaces the pcap backend can capture on.
+pub fn list_interfaces() -> anyhow::Result<Vec<Interface>> {
+    #[cfg(feature = "pcap")]
+    {
+        pcap_backend::list_interfaces()
+    }
+    #[cfg(not(feature = "pcap"))]
+    {
+        Err(anyhow!("Pcap capture not supported on this build"))
+    }
 }
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq, ValueEnum)]
@@ -51,6 +410,29 @@ pub trait CaptureBackend: Send {
 pub enum BackendType {
     Pktmon,
     Pcap,
+    /// Reads a pcap stream from a privileged capturer, so Irminsul itself
+    /// doesn't need capture permissions.
+    Pipe,
+    /// Captures through the installed capture helper.
+    Helper,
+}
+
+impl BackendType {
+    /// Backend to capture with when this one can't be started.
+    pub fn fallback(self) -> Option<BackendType> {
+        match self {
+            BackendType::Pktmon => cfg!(feature = "pcap").then_some(BackendType::Pcap),
+            BackendType::Pcap | BackendType::Pipe | BackendType::Helper => None,
+        }
+    }
+
+    /// Whether Irminsul needs capture permissions to use this backend.
+    pub fn needs_privileges(self) -> bool {
+        match self {
+            BackendType::Pktmon | BackendType::Pcap => true,
+            BackendType::Pipe | BackendType::Helper => false,
+        }
+    }
 }
 
 #[cfg(windows)]
@@ -58,12 +440,17 @@ pub const DEFAULT_CAPTURE_BACKEND_TYPE: BackendType = BackendType::Pktmon;
 #[cfg(not(windows))]
 pub const DEFAULT_CAPTURE_BACKEND_TYPE: BackendType = BackendType::Pcap;
 
-pub fn create_capture(backend: BackendType) -> Result<Box<dyn CaptureBackend>> {
+pub fn create_capture(
+    backend: BackendType,
+    settings: &CaptureSettings,
+) -> Result<Box<dyn CaptureBackend>> {
     match backend {
         BackendType::Pktmon => {
             #[cfg(windows)]
             {
-                Ok(Box::new(pktmon_backend::PktmonBackend::new()?))
+                Ok(Box::new(pktmon_backend::PktmonBackend::new(
+                    &settings.ports,
+                )?))
             }
             #[cfg(not(windows))]
             {
@@ -77,7 +464,7 @@ pub fn create_capture(backend: BackendType) -> Result<Box<dyn CaptureBackend>> {
         BackendType::Pcap => {
             #[cfg(feature = "pcap")]
             {
-                Ok(Box::new(pcap_backend::PcapBackend::new()?))
+                Ok(Box::new(pcap_backend::PcapBackend::new(settings)?))
             }
             #[cfg(not(feature = "pcap"))]
             {
@@ -87,5 +474,146 @@ pub fn create_capture(backend: BackendType) -> Result<Box<dyn CaptureBackend>> {
                 })
             }
         }
+
+        BackendType::Pipe => Ok(Box::new(pipe_backend::PipeBackend::new(settings)?)),
+
+        BackendType::Helper => {
+            #[cfg(unix)]
+            {
+                Ok(Box::new(helper::HelperBackend::new(settings)?))
+            }
+            #[cfg(not(unix))]
+            {
+                Err(CaptureError::Capture {
+                    has_captured: false,
+                    error: anyhow::anyhow!(
+                        "The capture helper is not supported on this operating system"
+                    ),
+                })
+            }
+        }
+    }
+}
+
+/// Create a capture with `backend`, or with its fallback when that fails and
+/// the settings allow it.  Returns the backend that ended up being used.
+pub fn create_capture_with_fallback(
+    backend: BackendType,
+    settings: &CaptureSettings,
+) -> Result<(BackendType, Box<dyn CaptureBackend>)> {
+    let error = match create_capture(backend, settings) {
+        Ok(capture) => return Ok((backend, capture)),
+        Err(e) => e,
+    };
+    let Some(fallback) = backend.fallback().filter(|_| settings.fallback) else {
+        return Err(error);
+    };
+
+    tracing::warn!("Unable to capture with {backend:?}, falling back to {fallback:?}: {error}");
+    match create_capture(fallback, settings) {
+        Ok(capture) => Ok((fallback, capture)),
+        Err(fallback_error) => Err(CaptureError::Capture {
+            has_captured: false,
+            error: anyhow!("{error}; {fallback:?} fallback: {fallback_error}"),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn port_sets_round_trip() {
+        let ports: PortSet = "22101-22102, 22201".parse().unwrap();
+        assert_eq!(ports.to_string(), "22101-22102,22201");
+        assert!(ports.contains(22102));
+        assert!(ports.contains(22201));
+        assert!(!ports.contains(22103));
+        assert_eq!(
+            ports.bpf_filter(),
+            "(udp and (portrange 22101-22102 or port 22201 or ip[6:2] & 0x1fff != 0)) \
+             or ip6[6] == 44"
+        );
+
+        assert_eq!(
+            "22101-22103,22102".parse::<PortSet>().unwrap().ports(),
+            BTreeSet::from([22101, 22102, 22103])
+        );
+
+        assert!("".parse::<PortSet>().is_err());
+        assert!("22102-22101".parse::<PortSet>().is_err());
+        assert!("70000".parse::<PortSet>().is_err());
+    }
+
+    #[test]
+    fn command_line_settings_override_saved_ones() {
+        let saved = CaptureSettings {
+            ports: "22101".parse().unwrap(),
+            filter: Some("udp".to_string()),
+            interfaces: vec!["eth0".to_string()],
+            fallback: false,
+            pipe: None,
+        };
+        assert_eq!(CaptureOverrides::default().apply(&saved), saved);
+
+        let overrides = CaptureOverrides {
+            ports: Some("22201".parse().unwrap()),
+            filter: None,
+            interfaces: vec!["wlan0".to_string()],
+            pipe: None,
+        };
+        let settings = overrides.apply(&saved);
+        assert_eq!(settings.ports.to_string(), "22201");
+        assert_eq!(settings.filter.as_deref(), Some("udp"));
+        assert_eq!(settings.interfaces, ["wlan0"]);
+        assert!(!settings.fallback);
+    }
+
+    #[test]
+    fn settings_saved_before_fallback_existed_fall_back() {
+        let settings: CaptureSettings = serde_json::from_str(r#"{"ports": "22101"}"#).unwrap();
+        assert!(settings.fallback);
+    }
+
+    #[test]
+    fn the_pktmon_port_limit_only_applies_to_pktmon() {
+        let mut settings = CaptureSettings {
+            ports: "22000-22200".parse().unwrap(),
+            fallback: false,
+            ..CaptureSettings::default()
+        };
+        assert!(settings.validate(BackendType::Pktmon).is_err());
+        assert!(settings.validate(BackendType::Pcap).is_ok());
+        assert!(settings.validate(BackendType::Pipe).is_ok());
+        assert!(settings.validate(BackendType::Helper).is_ok());
+
+        // Pcap captures instead when it's available.
+        settings.fallback = true;
+        assert_eq!(
+            settings.validate(BackendType::Pktmon).is_ok(),
+            cfg!(feature = "pcap")
+        );
+    }
+
+    #[test]
+    fn packet_direction_comes_from_the_server_port() {
+        let ports = PortSet::default();
+        let datagram = |source: &str, destination: &str| Datagram {
+            source: source.parse().unwrap(),
+            destination: destination.parse().unwrap(),
+            payload: Vec::new(),
+        };
+        let new_packet = |datagram| Packet::new(datagram, &ports, SystemTime::now(), None);
+
+        let packet = new_packet(datagram("10.0.0.2:50000", "203.0.113.7:22101")).unwrap();
+        assert_eq!(packet.direction, Direction::ClientToServer);
+        assert_eq!(packet.client(), "10.0.0.2:50000".parse().unwrap());
+
+        let packet = new_packet(datagram("203.0.113.7:22102", "10.0.0.2:50000")).unwrap();
+        assert_eq!(packet.direction, Direction::ServerToClient);
+        assert_eq!(packet.server(), "203.0.113.7:22102".parse().unwrap());
+
+        assert!(new_packet(datagram("10.0.0.2:50000", "203.0.113.7:53")).is_none());
     }
 }
@@ -0,0 +1,540 @@
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::{Duration, Instant};
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const ETHERTYPE_QINQ: u16 = 0x88a8;
+
+const IP_PROTOCOL_UDP: u8 = 17;
+const IPV6_HOP_BY_HOP: u8 = 0;
+const IPV6_ROUTING: u8 = 43;
+const IPV6_FRAGMENT: u8 = 44;
+const IPV6_DESTINATION_OPTIONS: u8 = 60;
+
+const ETHERNET_HEADER_LEN: usize = 14;
+const LINUX_SLL_HEADER_LEN: usize = 16;
+const LINUX_SLL2_HEADER_LEN: usize = 20;
+const LOOPBACK_HEADER_LEN: usize = 4;
+const IPV4_HEADER_LEN: usize = 20;
+const IPV6_HEADER_LEN: usize = 40;
+const UDP_HEADER_LEN: usize = 8;
+
+/// Fragments of a datagram that doesn't complete in this long are dropped.
+const FRAGMENT_TIMEOUT: Duration = Duration::from_secs(30);
+/// Datagrams being reassembled at once, to bound memory on lossy links.
+const MAX_PENDING_DATAGRAMS: usize = 64;
+
+/// Link layer of a capture, which decides where the IP packet in each frame
+/// starts.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LinkType {
+    Ethernet,
+    /// Linux "any" device captures.
+    LinuxSll,
+    LinuxSll2,
+    /// BSD loopback encapsulation, used by loopback devices on macOS,
+    /// Windows and the BSDs.
+    Loopback,
+    /// Bare IPv4 or IPv6 packets.
+    Raw,
+}
+
+/// An IP packet carrying a UDP datagram or a fragment of one.
+#[derive(Debug)]
+pub struct IpPacket<'a> {
+    pub source: IpAddr,
+    pub destination: IpAddr,
+    pub fragment: Option<Fragment>,
+    /// The packet's payload.  For unfragmented packets and first fragments
+    /// this starts with the UDP header.
+    pub payload: &'a [u8],
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Fragment {
+    pub id: u32,
+    /// Byte offset of this fragment's payload in the datagram.
+    pub offset: usize,
+    pub more: bool,
+}
+
+/// A complete UDP datagram.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Datagram {
+    pub source: SocketAddr,
+    pub destination: SocketAddr,
+    pub payload: Vec<u8>,
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(
+        bytes.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        bytes.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+/// Parse the IP packet out of a frame, or `None` if it isn't carrying UDP.
+pub fn parse_ip(link_type: LinkType, frame: &[u8]) -> Option<IpPacket<'_>> {
+    let ip = match link_type {
+        LinkType::Ethernet => {
+            let mut offset = ETHERNET_HEADER_LEN - 2;
+            let mut ethertype = read_u16(frame, offset)?;
+            while ethertype == ETHERTYPE_VLAN || ethertype == ETHERTYPE_QINQ {
+                offset += 4;
+                ethertype = read_u16(frame, offset)?;
+            }
+            is_ip_ethertype(ethertype).then_some(frame.get(offset + 2..)?)?
+        }
+        LinkType::LinuxSll => {
+            let ethertype = read_u16(frame, LINUX_SLL_HEADER_LEN - 2)?;
+            is_ip_ethertype(ethertype).then_some(frame.get(LINUX_SLL_HEADER_LEN..)?)?
+        }
+        LinkType::LinuxSll2 => {
+            let ethertype = read_u16(frame, 0)?;
+            is_ip_ethertype(ethertype).then_some(frame.get(LINUX_SLL2_HEADER_LEN..)?)?
+        }
+        // The address family is in host byte order and its value differs
+        // between platforms, so go by the IP version instead.
+        LinkType::Loopback => frame.get(LOOPBACK_HEADER_LEN..)?,
+        LinkType::Raw => frame,
+    };
+
+    match ip.first()? >> 4 {
+        4 => parse_ipv4(ip),
+        6 => parse_ipv6(ip),
+        _ => None,
+    }
+}
+
+fn is_ip_ethertype(ethertype: u16) -> bool {
+    ethertype == ETHERTYPE_IPV4 || ethertype == ETHERTYPE_IPV6
+}
+
+fn parse_ipv4(ip: &[u8]) -> Option<IpPacket<'_>> {
+    let header_len = usize::from(ip.first()? & 0x0f) * 4;
+    // Frames can be padded past the end of the packet.
+    let total_len = usize::from(read_u16(ip, 2)?);
+    if header_len < IPV4_HEADER_LEN || total_len < header_len || *ip.get(9)? != IP_PROTOCOL_UDP {
+        return None;
+    }
+
+    let flags = read_u16(ip, 6)?;
+    let more = flags & 0x2000 != 0;
+    let offset = usize::from(flags & 0x1fff) * 8;
+    let fragment = (more || offset != 0).then(|| Fragment {
+        id: u32::from(read_u16(ip, 4).unwrap_or_default()),
+        offset,
+        more,
+    });
+
+    Some(IpPacket {
+        source: IpAddr::V4(Ipv4Addr::from(read_u32(ip, 12)?)),
+        destination: IpAddr::V4(Ipv4Addr::from(read_u32(ip, 16)?)),
+        fragment,
+        payload: ip.get(header_len..total_len)?,
+    })
+}
+
+fn parse_ipv6(ip: &[u8]) -> Option<IpPacket<'_>> {
+    let payload_len = usize::from(read_u16(ip, 4)?);
+    let address = |offset: usize| -> Option<IpAddr> {
+        let bytes: [u8; 16] = ip.get(offset..offset + 16)?.try_into().ok()?;
+        Some(IpAddr::V6(Ipv6Addr::from(bytes)))
+    };
+    let source = address(8)?;
+    let destination = address(24)?;
+
+    let mut next_header = *ip.get(6)?;
+    let mut payload = ip.get(IPV6_HEADER_LEN..IPV6_HEADER_LEN + payload_len)?;
+    let mut fragment = None;
+    loop {
+        match next_header {
+            IP_PROTOCOL_UDP => break,
+            IPV6_HOP_BY_HOP | IPV6_ROUTING | IPV6_DESTINATION_OPTIONS => {
+                let len = (usize::from(*payload.get(1)?) + 1) * 8;
+                next_header = *payload.first()?;
+                payload = payload.get(len..)?;
+            }
+            IPV6_FRAGMENT => {
+                let flags = read_u16(payload, 2)?;
+                let offset = usize::from(flags >> 3) * 8;
+                let more = flags & 1 != 0;
+                if more || offset != 0 {
+                    fragment = Some(Fragment {
+                        id: read_u32(payload, 4)?,
+                        offset,
+                        more,
+                    });
+                }
+                next_header = *payload.first()?;
+                payload = payload.get(8..)?;
+            }
+            _ => return None,
+        }
+    }
+
+    Some(IpPacket {
+        source,
+        destination,
+        fragment,
+        payload,
+    })
+}
+
+impl Datagram {
+    /// Parse the UDP header at the start of a complete datagram.
+    fn from_udp(source: IpAddr, destination: IpAddr, udp: &[u8]) -> Option<Self> {
+        let len = usize::from(read_u16(udp, 4)?);
+        Some(Self {
+            source: SocketAddr::new(source, read_u16(udp, 0)?),
+            destination: SocketAddr::new(destination, read_u16(udp, 2)?),
+            payload: udp.get(UDP_HEADER_LEN..len.max(UDP_HEADER_LEN))?.to_vec(),
+        })
+    }
+
+    /// Build an Ethernet frame carrying this datagram unfragmented, which is
+    /// the form the sniffer expects packets in whatever the link type or
+    /// fragmentation they were captured with.
+    ///
+    /// The MAC addresses are zeroed and the UDP checksum left out, neither of
+    /// which the sniffer looks at.
+    pub fn to_ethernet_frame(&self) -> Vec<u8> {
+        let udp_len = UDP_HEADER_LEN + self.payload.len();
+        let mut frame = vec![0; ETHERNET_HEADER_LEN - 2];
+
+        match (self.source.ip(), self.destination.ip()) {
+            (IpAddr::V4(source), IpAddr::V4(destination)) => {
+                frame.extend_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+                let mut header = [0; IPV4_HEADER_LEN];
+                header[0] = 0x45;
+                header[2..4].copy_from_slice(&((IPV4_HEADER_LEN + udp_len) as u16).to_be_bytes());
+                header[8] = 64;
+                header[9] = IP_PROTOCOL_UDP;
+                header[12..16].copy_from_slice(&source.octets());
+                header[16..20].copy_from_slice(&destination.octets());
+                let checksum = ipv4_checksum(&header);
+                header[10..12].copy_from_slice(&checksum.to_be_bytes());
+                frame.extend_from_slice(&header);
+            }
+            (source, destination) => {
+                let to_v6 = |addr: IpAddr| match addr {
+                    IpAddr::V4(addr) => addr.to_ipv6_mapped(),
+                    IpAddr::V6(addr) => addr,
+                };
+                frame.extend_from_slice(&ETHERTYPE_IPV6.to_be_bytes());
+                let mut header = [0; IPV6_HEADER_LEN];
+                header[0] = 0x60;
+                header[4..6].copy_from_slice(&(udp_len as u16).to_be_bytes());
+                header[6] = IP_PROTOCOL_UDP;
+                header[7] = 64;
+                header[8..24].copy_from_slice(&to_v6(source).octets());
+                header[24..40].copy_from_slice(&to_v6(destination).octets());
+                frame.extend_from_slice(&header);
+            }
+        }
+
+        frame.extend_from_slice(&self.source.port().to_be_bytes());
+        frame.extend_from_slice(&self.destination.port().to_be_bytes());
+        frame.extend_from_slice(&(udp_len as u16).to_be_bytes());
+        frame.extend_from_slice(&[0, 0]);
+        frame.extend_from_slice(&self.payload);
+        frame
+    }
+}
+
+fn ipv4_checksum(header: &[u8]) -> u16 {
+    let sum = header
+        .chunks(2)
+        .map(|word| u32::from(u16::from_be_bytes([word[0], word[1]])))
+        .sum::<u32>();
+    let sum = (sum & 0xffff) + (sum >> 16);
+    !(((sum & 0xffff) + (sum >> 16)) as u16)
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+struct FragmentKey {
+    source: IpAddr,
+    destination: IpAddr,
+    id: u32,
+}
+
+#[derive(Debug)]
+struct PendingDatagram {
+    started: Instant,
+    /// Fragment payloads by offset.
+    parts: BTreeMap<usize, Vec<u8>>,
+    /// Known once the last fragment arrives.
+    total_len: Option<usize>,
+}
+
+impl PendingDatagram {
+    /// Whether a fragment of `len` bytes at `offset` fits what has arrived
+    /// so far.  Fragments ending past the datagram's known end, or a last
+    /// fragment ending before data already received, can only come from a
+    /// corrupt or crafted datagram.
+    fn fits(&self, offset: usize, len: usize, last: bool) -> bool {
+        let end = offset + len;
+        if let Some(total_len) = self.total_len
+            && end > total_len
+        {
+            return false;
+        }
+        !last
+            || self
+                .parts
+                .iter()
+                .all(|(part_offset, part)| part_offset + part.len() <= end)
+    }
+
+    /// The reassembled datagram, if every byte of it has arrived.
+    fn assemble(&self) -> Option<Vec<u8>> {
+        let total_len = self.total_len?;
+        let mut covered = 0;
+        for (offset, part) in self.parts.range(..total_len) {
+            if *offset > covered {
+                return None;
+            }
+            covered = covered.max(offset + part.len());
+        }
+        if covered < total_len {
+            return None;
+        }
+
+        let mut datagram = vec![0; total_len];
+        for (offset, part) in self.parts.range(..total_len) {
+            let end = (offset + part.len()).min(total_len);
+            datagram[*offset..end].copy_from_slice(&part[..end - offset]);
+        }
+        Some(datagram)
+    }
+}
+
+/// Reassembles datagrams split across IP fragments.
+#[derive(Debug, Default)]
+pub struct Reassembler {
+    pending: HashMap<FragmentKey, PendingDatagram>,
+}
+
+impl Reassembler {
+    /// Add a packet, returning the datagram it completes if any.
+    pub fn push(&mut self, packet: IpPacket<'_>) -> Option<Datagram> {
+        let Some(fragment) = packet.fragment else {
+            return Datagram::from_udp(packet.source, packet.destination, packet.payload);
+        };
+
+        self.pending
+            .retain(|_, pending| pending.started.elapsed() < FRAGMENT_TIMEOUT);
+        if self.pending.len() >= MAX_PENDING_DATAGRAMS
+            && let Some(oldest) = self
+                .pending
+                .iter()
+                .min_by_key(|(_, pending)| pending.started)
+                .map(|(key, _)| *key)
+        {
+            tracing::debug!("Dropping incomplete fragmented datagram {oldest:?}");
+            self.pending.remove(&oldest);
+        }
+
+        let key = FragmentKey {
+            source: packet.source,
+            destination: packet.destination,
+            id: fragment.id,
+        };
+        let pending = self.pending.entry(key).or_insert_with(|| PendingDatagram {
+            started: Instant::now(),
+            parts: BTreeMap::new(),
+            total_len: None,
+        });
+        if !pending.fits(fragment.offset, packet.payload.len(), !fragment.more) {
+            tracing::debug!("Dropping inconsistently fragmented datagram {key:?}");
+            self.pending.remove(&key);
+            return None;
+        }
+        pending
+            .parts
+            .insert(fragment.offset, packet.payload.to_vec());
+        if !fragment.more {
+            pending.total_len = Some(fragment.offset + packet.payload.len());
+        }
+
+        let datagram = pending.assemble()?;
+        self.pending.remove(&key);
+        Datagram::from_udp(key.source, key.destination, &datagram)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn datagram(payload: &[u8]) -> Datagram {
+        Datagram {
+            source: "10.0.0.2:50000".parse().unwrap(),
+            destination: "203.0.113.7:22101".parse().unwrap(),
+            payload: payload.to_vec(),
+        }
+    }
+
+    /// Split a datagram's IPv4 frame into fragments of `size` payload bytes.
+    fn ipv4_fragments(datagram: &Datagram, size: usize) -> Vec<Vec<u8>> {
+        let frame = datagram.to_ethernet_frame();
+        let (headers, udp) = frame.split_at(ETHERNET_HEADER_LEN + IPV4_HEADER_LEN);
+        udp.chunks(size)
+            .enumerate()
+            .map(|(i, chunk)| {
+                let mut fragment = headers.to_vec();
+                let ip = &mut fragment[ETHERNET_HEADER_LEN..];
+                ip[2..4].copy_from_slice(&((IPV4_HEADER_LEN + chunk.len()) as u16).to_be_bytes());
+                ip[4..6].copy_from_slice(&0x1234_u16.to_be_bytes());
+                let more = if (i + 1) * size < udp.len() {
+                    0x2000
+                } else {
+                    0
+                };
+                ip[6..8].copy_from_slice(&(more | (i * size / 8) as u16).to_be_bytes());
+                fragment.extend_from_slice(chunk);
+                fragment
+            })
+            .collect()
+    }
+
+    #[test]
+    fn normalized_frames_parse_back_to_the_datagram() {
+        let mut reassembler = Reassembler::default();
+        let ipv4 = datagram(&[1, 2, 3]);
+        let frame = ipv4.to_ethernet_frame();
+        let packet = parse_ip(LinkType::Ethernet, &frame).unwrap();
+        assert_eq!(packet.fragment, None);
+        assert_eq!(reassembler.push(packet), Some(ipv4));
+
+        let ipv6 = Datagram {
+            source: "[2001:db8::2]:50000".parse().unwrap(),
+            destination: "[2001:db8::7]:22101".parse().unwrap(),
+            payload: vec![4, 5, 6],
+        };
+        let frame = ipv6.to_ethernet_frame();
+        let packet = parse_ip(LinkType::Ethernet, &frame).unwrap();
+        assert_eq!(reassembler.push(packet), Some(ipv6));
+    }
+
+    #[test]
+    fn link_layers_are_skipped() {
+        let datagram = datagram(&[1, 2, 3]);
+        let ip = &datagram.to_ethernet_frame()[ETHERNET_HEADER_LEN..];
+
+        let mut sll = vec![0; LINUX_SLL_HEADER_LEN - 2];
+        sll.extend_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+        sll.extend_from_slice(ip);
+        let mut sll2 = ETHERTYPE_IPV4.to_be_bytes().to_vec();
+        sll2.resize(LINUX_SLL2_HEADER_LEN, 0);
+        sll2.extend_from_slice(ip);
+        let mut loopback = 2_u32.to_le_bytes().to_vec();
+        loopback.extend_from_slice(ip);
+        let mut vlan = vec![0; ETHERNET_HEADER_LEN - 2];
+        vlan.extend_from_slice(&ETHERTYPE_VLAN.to_be_bytes());
+        vlan.extend_from_slice(&[0, 1]);
+        vlan.extend_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+        vlan.extend_from_slice(ip);
+
+        for (link_type, frame) in [
+            (LinkType::LinuxSll, &sll[..]),
+            (LinkType::LinuxSll2, &sll2[..]),
+            (LinkType::Loopback, &loopback[..]),
+            (LinkType::Raw, ip),
+            (LinkType::Ethernet, &vlan[..]),
+        ] {
+            let packet = parse_ip(link_type, frame).unwrap();
+            assert_eq!(packet.payload.len(), UDP_HEADER_LEN + 3, "{link_type:?}");
+        }
+    }
+
+    #[test]
+    fn fragments_are_reassembled_in_any_order() {
+        let datagram = datagram(&(0..100).collect::<Vec<u8>>());
+        let mut fragments = ipv4_fragments(&datagram, 32);
+        assert_eq!(fragments.len(), 4);
+        fragments.swap(0, 3);
+
+        let mut reassembler = Reassembler::default();
+        let (last, rest) = fragments.split_last().unwrap();
+        for fragment in rest {
+            let packet = parse_ip(LinkType::Ethernet, fragment).unwrap();
+            assert!(packet.fragment.is_some());
+            assert_eq!(reassembler.push(packet), None);
+        }
+        let packet = parse_ip(LinkType::Ethernet, last).unwrap();
+        assert_eq!(reassembler.push(packet), Some(datagram));
+        assert!(reassembler.pending.is_empty());
+    }
+
+    /// Push a fragment of `len` bytes at `offset`, returning whether its
+    /// datagram is still being reassembled.
+    fn push_fragment(reassembler: &mut Reassembler, offset: usize, len: usize, more: bool) -> bool {
+        let payload = vec![0; len];
+        let packet = IpPacket {
+            source: "10.0.0.2".parse().unwrap(),
+            destination: "203.0.113.7".parse().unwrap(),
+            fragment: Some(Fragment {
+                id: 1,
+                offset,
+                more,
+            }),
+            payload: &payload,
+        };
+        assert_eq!(reassembler.push(packet), None);
+        !reassembler.pending.is_empty()
+    }
+
+    #[test]
+    fn a_last_fragment_before_received_data_drops_the_datagram() {
+        let mut reassembler = Reassembler::default();
+        assert!(push_fragment(&mut reassembler, 0, 64, true));
+        assert!(push_fragment(&mut reassembler, 56, 8, true));
+        assert!(!push_fragment(&mut reassembler, 16, 8, false));
+    }
+
+    #[test]
+    fn fragments_past_the_end_drop_the_datagram() {
+        let mut reassembler = Reassembler::default();
+        assert!(push_fragment(&mut reassembler, 16, 8, false));
+        assert!(!push_fragment(&mut reassembler, 8, 24, true));
+
+        // Overlapping fragments that stay inside the datagram are kept.
+        assert!(push_fragment(&mut reassembler, 16, 8, false));
+        assert!(push_fragment(&mut reassembler, 8, 16, true));
+    }
+
+    #[test]
+    fn parts_past_the_end_are_not_assembled() {
+        let pending = PendingDatagram {
+            started: Instant::now(),
+            parts: BTreeMap::from([(0, vec![1; 16]), (24, vec![2; 8])]),
+            total_len: Some(16),
+        };
+        assert_eq!(pending.assemble(), Some(vec![1; 16]));
+    }
+
+    #[test]
+    fn non_udp_and_truncated_frames_are_skipped() {
+        let mut frame = datagram(&[1, 2, 3]).to_ethernet_frame();
+        assert!(parse_ip(LinkType::Ethernet, &frame[..30]).is_none());
+
+        // TCP
+        frame[ETHERNET_HEADER_LEN + 9] = 6;
+        assert!(parse_ip(LinkType::Ethernet, &frame).is_none());
+    }
+
+    #[test]
+    fn normalized_ipv4_headers_have_valid_checksums() {
+        let frame = datagram(&[1, 2, 3]).to_ethernet_frame();
+        let header = &frame[ETHERNET_HEADER_LEN..ETHERNET_HEADER_LEN + IPV4_HEADER_LEN];
+        assert_eq!(ipv4_checksum(header), 0);
+    }
+}
@@ -0,0 +1,214 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use tokio::sync::mpsc::{self, Receiver};
+
+use crate::capture::{
+    CaptureBackend, CaptureError, CaptureSettings, PACKET_QUEUE_LEN, Packet, Result, pipe_backend,
+};
+
+/// Where the capture helper listens, as set up by its systemd socket unit.
+pub const DEFAULT_HELPER_SOCKET: &str = "/run/irminsul-capture.sock";
+/// Group allowed to connect to the helper.  Clients receive the game traffic
+/// of whoever is playing, so access is limited to users added to it.
+pub const HELPER_GROUP: &str = "irminsul";
+/// Each client gets its own capture, so only this many are served at once.
+#[cfg(feature = "pcap")]
+const MAX_HELPER_CLIENTS: usize = 4;
+
+/// Captures through the capture helper: a copy of Irminsul installed once
+/// with capture permissions that streams the game's traffic to unprivileged
+/// clients.  Unlike the running executable, the helper isn't replaced by
+/// updates, so they don't drop its permissions.
+pub struct HelperBackend {
+    packet_rx: Receiver<Result<Packet>>,
+    dropped_packets: Arc<AtomicU64>,
+}
+
+impl HelperBackend {
+    pub fn new(settings: &CaptureSettings) -> Result<Self> {
+        let path = settings.helper_path();
+        let stream = std::os::unix::net::UnixStream::connect(&path)
+            .and_then(|stream| {
+                stream.set_nonblocking(true)?;
+                tokio::net::UnixStream::from_std(stream)
+            })
+            .map_err(|e| {
+                let hint = if e.kind() == std::io::ErrorKind::PermissionDenied {
+                    format!(" (is your user in the {HELPER_GROUP} group?)")
+                } else {
+                    String::new()
+                };
+                CaptureError::Capture {
+                    has_captured: false,
+                    error: anyhow!("Unable to connect to the capture helper on {path}: {e}{hint}"),
+                }
+            })?;
+        tracing::info!("Connected to the capture helper on {path}");
+
+        let (packet_tx, packet_rx) = mpsc::channel(PACKET_QUEUE_LEN);
+        let dropped_packets = Arc::new(AtomicU64::new(0));
+        let name = Arc::from(path);
+        let ports = settings.ports.clone();
+        let dropped = dropped_packets.clone();
+        tokio::spawn(async move {
+            let result = tokio::select! {
+                result = pipe_backend::read_stream(stream, &name, &ports, &packet_tx, &dropped) => result,
+                // The `HelperBackend` was dropped.
+                _ = packet_tx.closed() => return,
+            };
+            let error = match result {
+                Ok(()) => anyhow!("Capture helper disconnected"),
+                Err(e) => e.context("Capture helper disconnected"),
+            };
+            let _ = packet_tx
+                .send(Err(CaptureError::Capture {
+                    has_captured: true,
+                    error,
+                }))
+                .await;
+        });
+
+        Ok(Self {
+            packet_rx,
+            dropped_packets,
+        })
+    }
+}
+
+#[async_trait]
+impl CaptureBackend for HelperBackend {
+    fn dropped_packets(&self) -> u64 {
+        self.dropped_packets.load(Ordering::Relaxed)
+    }
+
+    async fn next_packet(&mut self) -> Result<Packet> {
+        match self.packet_rx.recv().await {
+            Some(Ok(packet)) => Ok(packet),
+            Some(Err(err)) => Err(err),
+            None => Err(CaptureError::CaptureClosed),
+        }
+    }
+}
+
+/// Run as the capture helper, serving a pcap stream of the game's traffic
+/// captured with `settings` to each client that connects.  Listens on the
+/// socket systemd passes in or, when not started by systemd, on the
+/// settings' pipe path.
+///
+/// Clients can't change what is captured, so the helper can't be used to
+/// sniff anything but the ports it was started with.
+#[cfg(feature = "pcap")]
+pub fn run_helper(settings: CaptureSettings) -> anyhow::Result<()> {
+    use crate::capture::pipe_backend::Listener;
+
+    let runtime = tokio::runtime::Runtime::new()?;
+    runtime.block_on(async {
+        let mut listener = match systemd_listener()? {
+            Some(listener) => {
+                tracing::info!("Listening on the socket passed by systemd");
+                listener
+            }
+            None => {
+                use std::os::unix::fs::PermissionsExt;
+
+                let path = settings.helper_path();
+                let listener = Listener::bind(&path)?;
+                // Clients run as the desktop user rather than as the helper,
+                // so let the helper's group in, as the socket unit does.
+                match nix::unistd::Group::from_name(HELPER_GROUP)? {
+                    Some(group) => std::os::unix::fs::chown(&path, None, Some(group.gid.as_raw()))?,
+                    None => {
+                        tracing::warn!("There is no {HELPER_GROUP} group, so only root can connect")
+                    }
+                }
+                std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o660))?;
+                tracing::info!("Listening on {path}");
+                listener
+            }
+        };
+        tracing::info!("Capturing ports {}", settings.ports);
+
+        let clients = Arc::new(tokio::sync::Semaphore::new(MAX_HELPER_CLIENTS));
+        loop {
+            match listener.accept().await {
+                Ok(stream) => {
+                    let Ok(permit) = clients.clone().try_acquire_owned() else {
+                        // Dropping the stream disconnects the client.
+                        tracing::warn!("Refusing client, already serving {MAX_HELPER_CLIENTS}");
+                        continue;
+                    };
+                    let settings = settings.clone();
+                    tokio::spawn(async move {
+                        serve_client(stream, settings).await;
+                        drop(permit);
+                    });
+                }
+                Err(e) => tracing::warn!("Unable to accept client: {e}"),
+            }
+        }
+    })
+}
+
+/// The socket systemd passed in when socket activated, following
+/// `sd_listen_fds(3)`.
+#[cfg(feature = "pcap")]
+fn systemd_listener() -> anyhow::Result<Option<pipe_backend::Listener>> {
+    use std::os::fd::{FromRawFd, RawFd};
+
+    const SD_LISTEN_FDS_START: RawFd = 3;
+
+    let env_u32 = |name| std::env::var(name).ok()?.parse::<u32>().ok();
+    if env_u32("LISTEN_PID") != Some(std::process::id()) || env_u32("LISTEN_FDS").unwrap_or(0) == 0
+    {
+        return Ok(None);
+    }
+
+    // SAFETY: systemd passed this socket to this process, and nothing else in
+    // it takes ownership of the descriptor.
+    let listener = unsafe { std::os::unix::net::UnixListener::from_raw_fd(SD_LISTEN_FDS_START) };
+    Ok(Some(pipe_backend::Listener::from_std(listener)?))
+}
+
+/// Stream the traffic captured with `settings` to a client until it
+/// disconnects.  Each client gets its own capture, so clients coming and
+/// going don't affect each other.
+#[cfg(feature = "pcap")]
+async fn serve_client(stream: tokio::net::UnixStream, settings: CaptureSettings) {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use crate::capture::{BackendType, create_capture};
+
+    tracing::info!("Client connected");
+    let (mut reader, mut writer) = stream.into_split();
+    let result = async {
+        let mut capture =
+            create_capture(BackendType::Pcap, &settings).map_err(|e| anyhow!("{e}"))?;
+        writer
+            .write_all(&pipe_backend::ethernet_stream_header())
+            .await?;
+        // Clients don't send anything, so a read finishing means the client
+        // has gone.
+        let mut buf = [0; 1];
+        loop {
+            let packet = tokio::select! {
+                packet = capture.next_packet() => packet.map_err(|e| anyhow!("{e}"))?,
+                _ = reader.read(&mut buf) => return anyhow::Ok(()),
+            };
+            writer
+                .write_all(&pipe_backend::ethernet_stream_record(
+                    packet.timestamp,
+                    &packet.datagram.to_ethernet_frame(),
+                ))
+                .await?;
+        }
+    }
+    .await;
+
+    match result {
+        Ok(()) => tracing::info!("Client disconnected"),
+        Err(e) => tracing::warn!("Client disconnected: {e:#}"),
+    }
+}
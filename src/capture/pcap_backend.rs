@@ -1,12 +1,45 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime};
+
 use anyhow::anyhow;
 use async_trait::async_trait;
-use pcap::{Active, Capture, ConnectionStatus, Device};
-use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use pcap::{Active, Capture, ConnectionStatus, Device, Linktype};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{self, Receiver, Sender};
+
+use crate::capture::datagram::{self, LinkType, Reassembler};
+use crate::capture::{
+    CaptureBackend, CaptureError, CaptureSettings, Interface, InterfaceFailure, PACKET_QUEUE_LEN,
+    Packet, PortSet, Result,
+};
 
-use crate::capture::{CaptureBackend, CaptureError, PORT_RANGE, Result};
+/// How long a read waits for a packet, in milliseconds.  Reads wake up this
+/// often on idle devices so their threads notice the backend was dropped.
+const READ_TIMEOUT_MS: i32 = 500;
 
 pub struct PcapBackend {
-    packet_rx: UnboundedReceiver<Result<Vec<u8>>>,
+    packet_rx: Receiver<Result<Packet>>,
+    interface_failures: Vec<InterfaceFailure>,
+    /// Packets the device threads dropped because the queue was full.
+    dropped_packets: Arc<AtomicU64>,
+}
+
+struct DeviceCapture {
+    identifier: String,
+    name: Arc<str>,
+    capture: Capture<Active>,
+    link_type: LinkType,
+}
+
+pub fn list_interfaces() -> anyhow::Result<Vec<Interface>> {
+    Ok(Device::list()?
+        .into_iter()
+        .map(|device| Interface {
+            name: device.name,
+            description: device.desc,
+        })
+        .collect())
 }
 
 impl PcapBackend {
@@ -18,11 +51,27 @@ impl PcapBackend {
         )
     }
 
+    fn link_type(linktype: Linktype) -> Option<LinkType> {
+        match linktype {
+            Linktype::ETHERNET => Some(LinkType::Ethernet),
+            Linktype::LINUX_SLL => Some(LinkType::LinuxSll),
+            Linktype::LINUX_SLL2 => Some(LinkType::LinuxSll2),
+            Linktype::NULL | Linktype::LOOP => Some(LinkType::Loopback),
+            Linktype::RAW | Linktype::IPV4 | Linktype::IPV6 => Some(LinkType::Raw),
+            _ => None,
+        }
+    }
+
     fn should_capture_on_device(device: &Device) -> bool {
         device.flags.connection_status == ConnectionStatus::Connected
     }
 
-    pub fn new() -> Result<Self> {
+    /// Capture on the devices named in the settings' interfaces, or every
+    /// connected device when there are none.
+    pub fn new(settings: &CaptureSettings) -> Result<Self> {
+        let filter_expression = settings.pcap_filter();
+        let interfaces = &settings.interfaces;
+
         // 1. Find all devices
         let devices = Device::list().map_err(|e| CaptureError::Capture {
             has_captured: false,
@@ -40,12 +89,25 @@ impl PcapBackend {
             );
         }
 
-        // 2. Try to set up capture on all of them (we expect some of them to fail)
+        let mut interface_failures: Vec<InterfaceFailure> = interfaces
+            .iter()
+            .filter(|name| !devices.iter().any(|device| device.name == **name))
+            .map(|name| InterfaceFailure {
+                interface: name.clone(),
+                reason: "Interface not found".to_string(),
+            })
+            .collect();
+
+        // 2. Try to set up capture on the chosen devices.  When capturing on
+        // every device some are expected to fail, so failures are only
+        // reported for devices that were picked.
         let mut successful_captures = Vec::new();
-        let filter_expression = format!("udp and portrange {}-{}", PORT_RANGE.0, PORT_RANGE.1);
+        tracing::info!("Capture filter: {filter_expression}");
 
         for device in devices {
-            if !Self::should_capture_on_device(&device) {
+            let picked = interfaces.contains(&device.name);
+            let capture_all = interfaces.is_empty() && Self::should_capture_on_device(&device);
+            if !(picked || capture_all) {
                 tracing::info!(
                     "Excluded device {} from capture",
                     PcapBackend::get_device_identifier(&device)
@@ -53,50 +115,80 @@ impl PcapBackend {
                 continue;
             }
 
+            let name = device.name.clone();
             match Self::setup_device_capture(device, &filter_expression) {
                 Ok(capture) => {
                     successful_captures.push(capture);
                 }
-                Err(_) => {
-                    // Ignore; we probably shouldn't have captured on that device anyways
+                Err(e) => {
+                    tracing::warn!("Unable to capture on device {name}: {e}");
+                    if picked {
+                        interface_failures.push(InterfaceFailure {
+                            interface: name,
+                            reason: e.to_string(),
+                        });
+                    }
                 }
             }
         }
 
         // 3. Handle capture results
         if successful_captures.is_empty() {
+            let reasons = interface_failures
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>();
             return Err(CaptureError::Capture {
                 has_captured: false,
-                error: anyhow!("No capture device available"),
+                error: if reasons.is_empty() {
+                    anyhow!("No capture device available")
+                } else {
+                    anyhow!("No capture device available: {}", reasons.join(", "))
+                },
             });
         }
 
         tracing::info!("Capturing on {} devices:", successful_captures.len());
-        for (i, (device_identifier, _)) in successful_captures.iter().enumerate() {
+        for (i, device_capture) in successful_captures.iter().enumerate() {
             tracing::info!(
                 "Capture device {}/{}: {}",
                 i + 1,
                 successful_captures.len(),
-                device_identifier
+                device_capture.identifier
             );
         }
 
         // 4. Set up packet loops for each successful capture
-        let (packet_tx, packet_rx) = mpsc::unbounded_channel();
+        let (packet_tx, packet_rx) = mpsc::channel(PACKET_QUEUE_LEN);
+        let dropped_packets = Arc::new(AtomicU64::new(0));
+        let live_devices = Arc::new(AtomicUsize::new(successful_captures.len()));
 
-        for (device_identifier, capture) in successful_captures {
+        for device_capture in successful_captures {
             let packet_tx = packet_tx.clone();
-            std::thread::spawn(move || Self::packet_loop(capture, packet_tx, device_identifier));
+            let ports = settings.ports.clone();
+            let dropped_packets = dropped_packets.clone();
+            let live_devices = live_devices.clone();
+            std::thread::spawn(move || {
+                Self::packet_loop(
+                    device_capture,
+                    ports,
+                    packet_tx,
+                    dropped_packets,
+                    live_devices,
+                )
+            });
         }
 
-        Ok(Self { packet_rx })
+        Ok(Self {
+            packet_rx,
+            interface_failures,
+            dropped_packets,
+        })
     }
 
-    fn setup_device_capture(
-        device: Device,
-        filter_expression: &str,
-    ) -> Result<(String, Capture<Active>)> {
-        let device_identifier = Self::get_device_identifier(&device);
+    fn setup_device_capture(device: Device, filter_expression: &str) -> Result<DeviceCapture> {
+        let identifier = Self::get_device_identifier(&device);
+        let name = Arc::from(device.name.as_str());
 
         let mut capture = Capture::from_device(device)
             .map_err(|e| CaptureError::Capture {
@@ -104,30 +196,88 @@ impl PcapBackend {
                 error: e.into(),
             })?
             .immediate_mode(true)
+            .timeout(READ_TIMEOUT_MS)
             .open()
             .map_err(|e| CaptureError::Capture {
                 has_captured: false,
                 error: e.into(),
             })?;
 
+        let linktype = capture.get_datalink();
+        let link_type = Self::link_type(linktype).ok_or_else(|| CaptureError::Capture {
+            has_captured: false,
+            error: anyhow!(
+                "Unsupported link type {}",
+                linktype
+                    .get_name()
+                    .unwrap_or_else(|_| linktype.0.to_string())
+            ),
+        })?;
+
         capture
             .filter(filter_expression, true)
             .map_err(|e| CaptureError::Filter(e.into()))?;
 
-        Ok((device_identifier, capture))
+        Ok(DeviceCapture {
+            identifier,
+            name,
+            capture,
+            link_type,
+        })
     }
 
+    /// Pass packets from one device on to the backend until it's dropped or
+    /// the device fails.  `live_devices` counts the devices still capturing,
+    /// and only the failure of the last one is reported as a capture error,
+    /// so one device going away doesn't restart capture on the rest.
     fn packet_loop(
-        mut capture: Capture<Active>,
-        packet_tx: UnboundedSender<Result<Vec<u8>>>,
-        device_identifier: String,
+        device_capture: DeviceCapture,
+        ports: PortSet,
+        packet_tx: Sender<Result<Packet>>,
+        dropped_packets: Arc<AtomicU64>,
+        live_devices: Arc<AtomicUsize>,
     ) {
+        let DeviceCapture {
+            identifier: device_identifier,
+            name,
+            mut capture,
+            link_type,
+        } = device_capture;
         let mut has_captured = false;
+        let mut reassembler = Reassembler::default();
         loop {
             match capture.next_packet() {
                 Ok(packet) => {
                     has_captured = true;
-                    if packet_tx.send(Ok(packet.data.to_vec())).is_err() {
+                    let timestamp = SystemTime::UNIX_EPOCH
+                        + Duration::new(
+                            packet.header.ts.tv_sec as u64,
+                            packet.header.ts.tv_usec as u32 * 1000,
+                        );
+                    // Frames holding part of a datagram are held on to until
+                    // the rest of it arrives, and traffic outside the game's
+                    // ports is dropped here so it never takes up queue space.
+                    let Some(packet) = datagram::parse_ip(link_type, packet.data)
+                        .and_then(|packet| reassembler.push(packet))
+                        .and_then(|datagram| {
+                            Packet::new(datagram, &ports, timestamp, Some(name.clone()))
+                        })
+                    else {
+                        continue;
+                    };
+                    let closed = if packet.is_handshake() {
+                        packet_tx.blocking_send(Ok(packet)).is_err()
+                    } else {
+                        match packet_tx.try_send(Ok(packet)) {
+                            Ok(()) => false,
+                            Err(TrySendError::Full(_)) => {
+                                dropped_packets.fetch_add(1, Ordering::Relaxed);
+                                false
+                            }
+                            Err(TrySendError::Closed(_)) => true,
+                        }
+                    };
+                    if closed {
                         // If the `PcapBackend` is dropped, the receiver side will be dropped, and
                         // `send` will return an error.  This is a signal to terminate this thread.
                         tracing::info!(
@@ -139,21 +289,27 @@ impl PcapBackend {
                     }
                 }
                 Err(pcap::Error::TimeoutExpired) => {
-                    // No packets arrived within the read timeout window (common on Windows/Npcap
-                    // for idle devices); this is expected and not fatal, so keep polling.
+                    // No packets arrived within `READ_TIMEOUT_MS`, which is expected on
+                    // idle devices, so keep polling unless the `PcapBackend` has been
+                    // dropped in the meantime.
+                    if packet_tx.is_closed() {
+                        break;
+                    }
                     continue;
                 }
                 Err(err) => {
-                    tracing::info!(
+                    tracing::warn!(
                         "Packet loop for device {} ending (has_captured: {}): capture error: {}",
                         device_identifier,
                         has_captured,
                         err
                     );
-                    let _ = packet_tx.send(Err(CaptureError::Capture {
-                        has_captured,
-                        error: err.into(),
-                    }));
+                    if live_devices.fetch_sub(1, Ordering::AcqRel) == 1 {
+                        let _ = packet_tx.blocking_send(Err(CaptureError::Capture {
+                            has_captured,
+                            error: err.into(),
+                        }));
+                    }
                     break;
                 }
             }
@@ -163,7 +319,15 @@ impl PcapBackend {
 
 #[async_trait]
 impl CaptureBackend for PcapBackend {
-    async fn next_packet(&mut self) -> Result<Vec<u8>> {
+    fn interface_failures(&self) -> &[InterfaceFailure] {
+        &self.interface_failures
+    }
+
+    fn dropped_packets(&self) -> u64 {
+        self.dropped_packets.load(Ordering::Relaxed)
+    }
+
+    async fn next_packet(&mut self) -> Result<Packet> {
         match self.packet_rx.recv().await {
             Some(Ok(packet)) => Ok(packet),
             Some(Err(err)) => Err(err),
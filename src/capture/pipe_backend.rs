@@ -0,0 +1,512 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime};
+
+use anyhow::{anyhow, bail};
+use async_trait::async_trait;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{self, Receiver, Sender};
+
+use crate::capture::datagram::{self, LinkType, Reassembler};
+use crate::capture::{
+    CaptureBackend, CaptureError, CaptureSettings, PACKET_QUEUE_LEN, Packet, PortSet, Result,
+};
+
+const PCAP_MAGIC_MICROS: u32 = 0xa1b2c3d4;
+const PCAP_MAGIC_NANOS: u32 = 0xa1b23c4d;
+const PCAPNG_MAGIC: u32 = 0x0a0d0d0a;
+const PCAP_HEADER_LEN: usize = 24;
+const RECORD_HEADER_LEN: usize = 16;
+/// Largest record accepted, well over the largest snapshot length capturers
+/// use, so a corrupt stream fails quickly instead of allocating gigabytes.
+const MAX_RECORD_LEN: usize = 256 * 1024;
+
+/// Captures from a pcap stream written to a Unix socket or, on Windows, a
+/// named pipe by a capturer running with the privileges Irminsul doesn't
+/// have, e.g. `tcpdump -U -w -` piped through `socat`.
+///
+/// Capturers can connect one after another, so the capturer can be restarted
+/// without restarting the capture.
+pub struct PipeBackend {
+    packet_rx: Receiver<Result<Packet>>,
+    dropped_packets: Arc<AtomicU64>,
+}
+
+/// Where the pipe backend listens when no path is given.
+pub fn default_pipe_path() -> String {
+    #[cfg(windows)]
+    {
+        r"\\.\pipe\irminsul-capture".to_string()
+    }
+    #[cfg(not(windows))]
+    {
+        default_socket_dir()
+            .join("irminsul-capture.sock")
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+/// Directory of the default socket.  The temp dir is shared between users,
+/// so without a runtime dir the socket goes in a directory of the user's own
+/// inside it.
+#[cfg(not(windows))]
+fn default_socket_dir() -> std::path::PathBuf {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => std::path::PathBuf::from(dir),
+        None => std::env::temp_dir().join(format!("irminsul-{}", nix::unistd::getuid())),
+    }
+}
+
+/// Create `dir` private to the current user, or check an existing one is
+/// the user's and closed to everyone else, so other users can't connect to
+/// the socket in it or take over its path first.
+#[cfg(unix)]
+fn ensure_private_dir(dir: &std::path::Path) -> std::io::Result<()> {
+    use std::io::{Error, ErrorKind};
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+
+    match std::fs::DirBuilder::new().mode(0o700).create(dir) {
+        Ok(()) => return Ok(()),
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => (),
+        Err(e) => return Err(e),
+    }
+    let metadata = std::fs::symlink_metadata(dir)?;
+    if !metadata.is_dir()
+        || metadata.uid() != nix::unistd::getuid().as_raw()
+        || metadata.mode() & 0o077 != 0
+    {
+        return Err(Error::new(
+            ErrorKind::PermissionDenied,
+            format!("{} isn't a directory private to this user", dir.display()),
+        ));
+    }
+    Ok(())
+}
+
+impl PipeBackend {
+    pub fn new(settings: &CaptureSettings) -> Result<Self> {
+        let path = settings.pipe_path();
+        let listener =
+            Self::listen(&path, settings.pipe.is_none()).map_err(|e| CaptureError::Capture {
+                has_captured: false,
+                error: anyhow!("Unable to listen on {path}: {e}"),
+            })?;
+        tracing::info!("Waiting for a pcap stream on {path}");
+
+        let (packet_tx, packet_rx) = mpsc::channel(PACKET_QUEUE_LEN);
+        let dropped_packets = Arc::new(AtomicU64::new(0));
+        tokio::spawn(Self::accept_loop(
+            listener,
+            Arc::from(path),
+            settings.ports.clone(),
+            packet_tx,
+            dropped_packets.clone(),
+        ));
+
+        Ok(Self {
+            packet_rx,
+            dropped_packets,
+        })
+    }
+
+    /// Listen on `path`, with the socket only open to the current user.  The
+    /// directory of the default path is checked to be private too.
+    fn listen(path: &str, is_default: bool) -> std::io::Result<Listener> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            if is_default && let Some(dir) = std::path::Path::new(path).parent() {
+                ensure_private_dir(dir)?;
+            }
+            let listener = Listener::bind(path)?;
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+            Ok(listener)
+        }
+        #[cfg(windows)]
+        {
+            let _ = is_default;
+            Listener::bind(path)
+        }
+    }
+
+    async fn accept_loop(
+        mut listener: Listener,
+        name: Arc<str>,
+        ports: PortSet,
+        packet_tx: Sender<Result<Packet>>,
+        dropped_packets: Arc<AtomicU64>,
+    ) {
+        loop {
+            // If the `PipeBackend` is dropped, the receiver side will be dropped, and
+            // `closed` will return.  This is a signal to stop listening.
+            let stream = tokio::select! {
+                stream = listener.accept() => stream,
+                _ = packet_tx.closed() => break,
+            };
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    let _ = packet_tx
+                        .send(Err(CaptureError::Capture {
+                            has_captured: false,
+                            error: e.into(),
+                        }))
+                        .await;
+                    break;
+                }
+            };
+
+            tracing::info!("Capturer connected to {name}");
+            let result = tokio::select! {
+                result = read_stream(stream, &name, &ports, &packet_tx, &dropped_packets) => result,
+                _ = packet_tx.closed() => break,
+            };
+            match result {
+                Ok(()) => tracing::info!("Capturer disconnected from {name}"),
+                Err(e) => tracing::warn!("Capturer disconnected from {name}: {e:#}"),
+            }
+        }
+        tracing::info!("No longer listening on {name}");
+    }
+}
+
+#[async_trait]
+impl CaptureBackend for PipeBackend {
+    fn dropped_packets(&self) -> u64 {
+        self.dropped_packets.load(Ordering::Relaxed)
+    }
+
+    async fn next_packet(&mut self) -> Result<Packet> {
+        match self.packet_rx.recv().await {
+            Some(Ok(packet)) => Ok(packet),
+            Some(Err(err)) => Err(err),
+            None => Err(CaptureError::CaptureClosed),
+        }
+    }
+}
+
+#[cfg(unix)]
+pub(super) struct Listener(tokio::net::UnixListener);
+
+#[cfg(unix)]
+impl Listener {
+    pub(super) fn bind(path: &str) -> std::io::Result<Self> {
+        use std::os::unix::fs::FileTypeExt;
+
+        // A socket left behind by an earlier run would stop the bind.
+        if std::fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+            std::fs::remove_file(path)?;
+        }
+        Ok(Self(tokio::net::UnixListener::bind(path)?))
+    }
+
+    #[cfg_attr(not(feature = "pcap"), allow(dead_code))]
+    pub(super) fn from_std(listener: std::os::unix::net::UnixListener) -> std::io::Result<Self> {
+        listener.set_nonblocking(true)?;
+        Ok(Self(tokio::net::UnixListener::from_std(listener)?))
+    }
+
+    pub(super) async fn accept(&mut self) -> std::io::Result<tokio::net::UnixStream> {
+        Ok(self.0.accept().await?.0)
+    }
+}
+
+#[cfg(windows)]
+struct Listener {
+    path: String,
+    server: tokio::net::windows::named_pipe::NamedPipeServer,
+}
+
+#[cfg(windows)]
+impl Listener {
+    fn bind(path: &str) -> std::io::Result<Self> {
+        use tokio::net::windows::named_pipe::ServerOptions;
+
+        Ok(Self {
+            path: path.to_string(),
+            server: ServerOptions::new()
+                .first_pipe_instance(true)
+                .create(path)?,
+        })
+    }
+
+    async fn accept(
+        &mut self,
+    ) -> std::io::Result<tokio::net::windows::named_pipe::NamedPipeServer> {
+        use tokio::net::windows::named_pipe::ServerOptions;
+
+        self.server.connect().await?;
+        // Each instance of a named pipe serves one client, so make the next
+        // one before handing this one over.
+        let next = ServerOptions::new().create(&self.path)?;
+        Ok(std::mem::replace(&mut self.server, next))
+    }
+}
+
+/// The global header at the start of a pcap stream.
+#[derive(Debug, Eq, PartialEq)]
+struct StreamHeader {
+    big_endian: bool,
+    /// Whether record timestamps have nanoseconds rather than microseconds.
+    nanos: bool,
+    link_type: LinkType,
+}
+
+impl StreamHeader {
+    fn parse(header: &[u8; PCAP_HEADER_LEN]) -> anyhow::Result<Self> {
+        let magic = u32::from_le_bytes(header[0..4].try_into().unwrap());
+        let (big_endian, magic) = match magic {
+            PCAP_MAGIC_MICROS | PCAP_MAGIC_NANOS => (false, magic),
+            _ => (true, magic.swap_bytes()),
+        };
+        let nanos = match magic {
+            PCAP_MAGIC_MICROS => false,
+            PCAP_MAGIC_NANOS => true,
+            PCAPNG_MAGIC => bail!("pcapng streams aren't supported, write pcap (e.g. -F pcap)"),
+            _ => bail!("Not a pcap stream"),
+        };
+
+        // The upper bits of the link type hold FCS details.
+        let link_type = match read_u32(&header[20..24], big_endian) & 0xffff {
+            1 => LinkType::Ethernet,
+            113 => LinkType::LinuxSll,
+            276 => LinkType::LinuxSll2,
+            0 | 108 => LinkType::Loopback,
+            101 | 228 | 229 => LinkType::Raw,
+            link_type => bail!("Unsupported link type {link_type}"),
+        };
+
+        Ok(Self {
+            big_endian,
+            nanos,
+            link_type,
+        })
+    }
+
+    /// Timestamp and captured length of a record.
+    fn parse_record_header(&self, header: &[u8; RECORD_HEADER_LEN]) -> (SystemTime, usize) {
+        let seconds = read_u32(&header[0..4], self.big_endian);
+        let fraction = read_u32(&header[4..8], self.big_endian);
+        let nanos = if self.nanos {
+            fraction
+        } else {
+            fraction.saturating_mul(1000)
+        };
+        let timestamp =
+            SystemTime::UNIX_EPOCH + Duration::new(seconds.into(), nanos.min(999_999_999));
+        (
+            timestamp,
+            read_u32(&header[8..12], self.big_endian) as usize,
+        )
+    }
+}
+
+fn read_u32(bytes: &[u8], big_endian: bool) -> u32 {
+    let bytes = bytes.try_into().unwrap();
+    if big_endian {
+        u32::from_be_bytes(bytes)
+    } else {
+        u32::from_le_bytes(bytes)
+    }
+}
+
+/// Global header of a pcap stream of Ethernet frames with microsecond
+/// timestamps.
+#[cfg_attr(not(feature = "pcap"), allow(dead_code))]
+pub(super) fn ethernet_stream_header() -> [u8; PCAP_HEADER_LEN] {
+    let mut header = [0; PCAP_HEADER_LEN];
+    header[0..4].copy_from_slice(&PCAP_MAGIC_MICROS.to_le_bytes());
+    // Version 2.4.
+    header[4..6].copy_from_slice(&2_u16.to_le_bytes());
+    header[6..8].copy_from_slice(&4_u16.to_le_bytes());
+    header[16..20].copy_from_slice(&(MAX_RECORD_LEN as u32).to_le_bytes());
+    header[20..24].copy_from_slice(&1_u32.to_le_bytes());
+    header
+}
+
+/// A record of a stream started with `ethernet_stream_header` holding
+/// `frame`.
+#[cfg_attr(not(feature = "pcap"), allow(dead_code))]
+pub(super) fn ethernet_stream_record(timestamp: SystemTime, frame: &[u8]) -> Vec<u8> {
+    let since_epoch = timestamp
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    let len = (frame.len() as u32).to_le_bytes();
+    let mut record = Vec::with_capacity(RECORD_HEADER_LEN + frame.len());
+    record.extend_from_slice(&(since_epoch.as_secs() as u32).to_le_bytes());
+    record.extend_from_slice(&since_epoch.subsec_micros().to_le_bytes());
+    record.extend_from_slice(&len);
+    record.extend_from_slice(&len);
+    record.extend_from_slice(frame);
+    record
+}
+
+/// Read packets from a pcap stream until it ends.
+pub(super) async fn read_stream(
+    mut stream: impl AsyncRead + Unpin,
+    name: &Arc<str>,
+    ports: &PortSet,
+    packet_tx: &Sender<Result<Packet>>,
+    dropped_packets: &AtomicU64,
+) -> anyhow::Result<()> {
+    let mut header = [0; PCAP_HEADER_LEN];
+    stream.read_exact(&mut header).await?;
+    let header = StreamHeader::parse(&header)?;
+    tracing::info!("Reading {:?} frames from {name}", header.link_type);
+
+    let mut reassembler = Reassembler::default();
+    let mut record_header = [0; RECORD_HEADER_LEN];
+    let mut frame = Vec::new();
+    loop {
+        match stream.read_exact(&mut record_header).await {
+            Ok(_) => (),
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e.into()),
+        }
+        let (timestamp, len) = header.parse_record_header(&record_header);
+        if len > MAX_RECORD_LEN {
+            bail!("Record of {len} bytes is too long");
+        }
+        frame.resize(len, 0);
+        stream.read_exact(&mut frame).await?;
+
+        // Frames holding part of a datagram are held on to until the rest of
+        // it arrives, and traffic outside the game's ports is dropped here so
+        // it never takes up queue space.
+        let Some(packet) = datagram::parse_ip(header.link_type, &frame)
+            .and_then(|packet| reassembler.push(packet))
+            .and_then(|datagram| Packet::new(datagram, ports, timestamp, Some(name.clone())))
+        else {
+            continue;
+        };
+        if packet.is_handshake() {
+            packet_tx.send(Ok(packet)).await?;
+        } else {
+            match packet_tx.try_send(Ok(packet)) {
+                Ok(()) => (),
+                Err(TrySendError::Full(_)) => {
+                    dropped_packets.fetch_add(1, Ordering::Relaxed);
+                }
+                Err(TrySendError::Closed(_)) => return Err(anyhow!("Channel closed")),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::datagram::Datagram;
+
+    fn stream_header(magic: u32, link_type: u32, big_endian: bool) -> [u8; PCAP_HEADER_LEN] {
+        let to_bytes = |value: u32| {
+            if big_endian {
+                value.to_be_bytes()
+            } else {
+                value.to_le_bytes()
+            }
+        };
+        let mut header = [0; PCAP_HEADER_LEN];
+        header[0..4].copy_from_slice(&to_bytes(magic));
+        header[20..24].copy_from_slice(&to_bytes(link_type));
+        header
+    }
+
+    #[test]
+    fn stream_headers_of_either_byte_order_parse() {
+        assert_eq!(
+            StreamHeader::parse(&stream_header(PCAP_MAGIC_MICROS, 1, false)).unwrap(),
+            StreamHeader {
+                big_endian: false,
+                nanos: false,
+                link_type: LinkType::Ethernet,
+            }
+        );
+        assert_eq!(
+            StreamHeader::parse(&stream_header(PCAP_MAGIC_NANOS, 113, true)).unwrap(),
+            StreamHeader {
+                big_endian: true,
+                nanos: true,
+                link_type: LinkType::LinuxSll,
+            }
+        );
+        assert!(StreamHeader::parse(&stream_header(PCAPNG_MAGIC, 1, false)).is_err());
+        assert!(StreamHeader::parse(&stream_header(PCAP_MAGIC_MICROS, 105, false)).is_err());
+    }
+
+    #[test]
+    fn record_timestamps_use_the_stream_precision() {
+        let mut record = [0; RECORD_HEADER_LEN];
+        record[0..4].copy_from_slice(&10_u32.to_le_bytes());
+        record[4..8].copy_from_slice(&500_u32.to_le_bytes());
+        record[8..12].copy_from_slice(&42_u32.to_le_bytes());
+
+        let header = StreamHeader::parse(&stream_header(PCAP_MAGIC_MICROS, 1, false)).unwrap();
+        assert_eq!(
+            header.parse_record_header(&record),
+            (SystemTime::UNIX_EPOCH + Duration::new(10, 500_000), 42)
+        );
+        let header = StreamHeader::parse(&stream_header(PCAP_MAGIC_NANOS, 1, false)).unwrap();
+        assert_eq!(
+            header.parse_record_header(&record).0,
+            SystemTime::UNIX_EPOCH + Duration::new(10, 500)
+        );
+    }
+
+    #[tokio::test]
+    async fn game_packets_are_read_from_the_stream() {
+        let datagram = |source: &str, destination: &str| Datagram {
+            source: source.parse().unwrap(),
+            destination: destination.parse().unwrap(),
+            payload: vec![1, 2, 3],
+        };
+        let timestamp = SystemTime::UNIX_EPOCH + Duration::new(10, 500_000);
+        let mut stream = ethernet_stream_header().to_vec();
+        for datagram in [
+            datagram("10.0.0.2:50000", "203.0.113.7:53"),
+            datagram("10.0.0.2:50000", "203.0.113.7:22101"),
+        ] {
+            stream.extend(ethernet_stream_record(
+                timestamp,
+                &datagram.to_ethernet_frame(),
+            ));
+        }
+
+        let (packet_tx, mut packet_rx) = mpsc::channel(PACKET_QUEUE_LEN);
+        let name = Arc::from("test");
+        read_stream(
+            stream.as_slice(),
+            &name,
+            &PortSet::default(),
+            &packet_tx,
+            &AtomicU64::new(0),
+        )
+        .await
+        .unwrap();
+
+        let packet = packet_rx.try_recv().unwrap().unwrap();
+        assert_eq!(packet.server(), "203.0.113.7:22101".parse().unwrap());
+        assert_eq!(packet.timestamp, timestamp);
+        assert_eq!(packet.datagram.payload, [1, 2, 3]);
+        assert!(packet_rx.try_recv().is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn socket_dirs_open_to_other_users_are_refused() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let private = dir.path().join("private");
+        ensure_private_dir(&private).unwrap();
+        let mode = std::fs::metadata(&private).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+        ensure_private_dir(&private).unwrap();
+
+        std::fs::set_permissions(&private, std::fs::Permissions::from_mode(0o777)).unwrap();
+        assert!(ensure_private_dir(&private).is_err());
+    }
+}
@@ -1,58 +1,72 @@
+use std::time::SystemTime;
+
 use async_trait::async_trait;
 use futures::StreamExt;
 use futures::stream::FusedStream;
+use pktmon::Capture;
 use pktmon::filter::{PktMonFilter, TransportProtocol};
-use pktmon::{Capture, Packet};
 
-use crate::capture::{CaptureBackend, CaptureError, PORT_RANGE, Result};
+use crate::capture::datagram::{self, LinkType, Reassembler};
+use crate::capture::{CaptureBackend, CaptureError, MAX_PKTMON_PORTS, Packet, PortSet, Result};
 
 pub struct PktmonBackend {
-    stream: Box<dyn FusedStream<Item = Packet> + Unpin + Send>,
+    stream: Box<dyn FusedStream<Item = pktmon::Packet> + Unpin + Send>,
+    ports: PortSet,
+    reassembler: Reassembler,
 }
 
 impl PktmonBackend {
-    pub fn new() -> Result<Self> {
+    pub fn new(ports: &PortSet) -> Result<Self> {
+        let port_count = ports.ports().len();
+        if port_count > MAX_PKTMON_PORTS {
+            return Err(CaptureError::Filter(anyhow::anyhow!(
+                "Pktmon can capture on at most {MAX_PKTMON_PORTS} ports, not {port_count}"
+            )));
+        }
+
         let mut capture = Capture::new().map_err(|e| CaptureError::Capture {
             has_captured: false,
             error: e.into(),
         })?;
 
-        let filter = PktMonFilter {
-            name: "UDP Filter".to_string(),
-            transport_protocol: Some(TransportProtocol::UDP),
-            port: PORT_RANGE.0.into(),
-            ..PktMonFilter::default()
-        };
-
-        capture
-            .add_filter(filter)
-            .map_err(|e| CaptureError::Filter(e.into()))?;
+        // Pktmon filters match a single port each.
+        for port in ports.ports() {
+            let filter = PktMonFilter {
+                name: "UDP Filter".to_string(),
+                transport_protocol: Some(TransportProtocol::UDP),
+                port: port.into(),
+                ..PktMonFilter::default()
+            };
 
-        let filter = PktMonFilter {
-            name: "UDP Filter".to_string(),
-            transport_protocol: Some(TransportProtocol::UDP),
-            port: PORT_RANGE.1.into(),
-            ..PktMonFilter::default()
-        };
-
-        capture
-            .add_filter(filter)
-            .map_err(|e| CaptureError::Filter(e.into()))?;
+            capture
+                .add_filter(filter)
+                .map_err(|e| CaptureError::Filter(e.into()))?;
+        }
 
         Ok(Self {
             stream: Box::new(capture.stream().unwrap().boxed().fuse()),
+            ports: ports.clone(),
+            reassembler: Reassembler::default(),
         })
     }
 }
 
 #[async_trait]
 impl CaptureBackend for PktmonBackend {
-    async fn next_packet(&mut self) -> Result<Vec<u8>> {
-        futures::select! {
-            packet = self.stream.select_next_some() => {
-                Ok(packet.payload.to_vec().clone())
-            },
-            complete => Err(CaptureError::CaptureClosed),
+    async fn next_packet(&mut self) -> Result<Packet> {
+        loop {
+            let packet = futures::select! {
+                packet = self.stream.select_next_some() => packet,
+                complete => return Err(CaptureError::CaptureClosed),
+            };
+
+            // Pktmon doesn't say which interface a packet was seen on.
+            if let Some(packet) = datagram::parse_ip(LinkType::Ethernet, &packet.payload.to_vec())
+                .and_then(|packet| self.reassembler.push(packet))
+                .and_then(|datagram| Packet::new(datagram, &self.ports, SystemTime::now(), None))
+            {
+                return Ok(packet);
+            }
         }
     }
 }
@@ -0,0 +1,232 @@
+use std::fmt::Display;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::player_data::{ExportSettings, PlayerData};
+use crate::spreadsheet::Sheet;
+
+mod enka;
+mod seelie;
+
+/// What an export produces.
+#[derive(Clone, Debug)]
+pub enum Export {
+    /// Text, copied to the clipboard or saved as a single file.
+    Text(String),
+    /// Tables, saved as a workbook or as one CSV file each.
+    Sheets(Vec<Sheet>),
+}
+
+/// A file format that player data can be exported to.
+pub trait Exporter {
+    /// Name of the tool the format is for.
+    fn name(&self) -> &'static str;
+
+    /// Prefix of the default file name exports are saved under.
+    fn file_prefix(&self) -> &'static str;
+
+    /// Names and extensions of the file types exports can be saved as, the
+    /// first being the default.
+    fn file_types(&self) -> &'static [(&'static str, &'static str)] {
+        &[("JSON files", "json")]
+    }
+
+    /// Whether exports can be copied to the clipboard.
+    fn can_copy(&self) -> bool {
+        true
+    }
+
+    fn export(&self, player_data: &PlayerData, settings: &ExportSettings) -> Result<Export>;
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub enum ExportFormat {
+    #[default]
+    GenshinOptimizer,
+    Seelie,
+    Enka,
+    Spreadsheet,
+}
+
+impl ExportFormat {
+    pub const ALL: [ExportFormat; 4] = [
+        ExportFormat::GenshinOptimizer,
+        ExportFormat::Seelie,
+        ExportFormat::Enka,
+        ExportFormat::Spreadsheet,
+    ];
+
+    pub fn exporter(&self) -> &'static dyn Exporter {
+        match self {
+            ExportFormat::GenshinOptimizer => &GenshinOptimizer,
+            ExportFormat::Seelie => &seelie::Seelie,
+            ExportFormat::Enka => &enka::Enka,
+            ExportFormat::Spreadsheet => &Spreadsheet,
+        }
+    }
+}
+
+impl Display for ExportFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.exporter().name())
+    }
+}
+
+/// Genshin Open Object Description (GOOD) used by Genshin Optimizer.
+struct GenshinOptimizer;
+
+impl Exporter for GenshinOptimizer {
+    fn name(&self) -> &'static str {
+        "Genshin Optimizer"
+    }
+
+    fn file_prefix(&self) -> &'static str {
+        "genshin_export"
+    }
+
+    fn export(&self, player_data: &PlayerData, settings: &ExportSettings) -> Result<Export> {
+        player_data
+            .export_genshin_optimizer(settings)
+            .map(Export::Text)
+    }
+}
+
+/// The same data as the GOOD export, laid out as tables with the names from
+/// the game for opening in a spreadsheet.
+struct Spreadsheet;
+
+impl Exporter for Spreadsheet {
+    fn name(&self) -> &'static str {
+        "Spreadsheet"
+    }
+
+    fn file_prefix(&self) -> &'static str {
+        "genshin_export"
+    }
+
+    fn file_types(&self) -> &'static [(&'static str, &'static str)] {
+        &[("Excel workbooks", "xlsx"), ("CSV files", "csv")]
+    }
+
+    fn can_copy(&self) -> bool {
+        false
+    }
+
+    fn export(&self, player_data: &PlayerData, settings: &ExportSettings) -> Result<Export> {
+        Ok(Export::Sheets(player_data.export_spreadsheet(settings)))
+    }
+}
+
+#[cfg(test)]
+mod fixtures {
+    use std::collections::HashMap;
+
+    use auto_artifactarium::r#gen::protos::{Equip, Material, PropValue, Reliquary, Weapon};
+
+    use super::Export;
+    use crate::player_data::{AvatarInfo, ExportSettings, Item, PlayerData};
+
+    pub const HU_TAO: u32 = 10000046;
+    pub const STAFF_OF_HOMA: u32 = 13501;
+    pub const HEROS_WIT: u32 = 104003;
+
+    /// The text of an export, panicking if it isn't one.
+    pub fn text(export: anyhow::Result<Export>) -> String {
+        match export.unwrap() {
+            Export::Text(text) => text,
+            export => panic!("Expected a text export, got {export:?}"),
+        }
+    }
+
+    pub fn settings() -> ExportSettings {
+        ExportSettings {
+            include_characters: true,
+            include_artifacts: true,
+            include_weapons: true,
+            include_materials: true,
+            fake_initialize_4th_line: false,
+            min_character_level: 1,
+            min_character_ascension: 0,
+            min_character_constellation: 0,
+            min_artifact_level: 0,
+            min_artifact_rarity: 0,
+            min_weapon_level: 1,
+            min_weapon_refinement: 0,
+            min_weapon_ascension: 0,
+            min_weapon_rarity: 0,
+        }
+    }
+
+    fn prop(type_: u32, val: i64) -> (u32, PropValue) {
+        (
+            type_,
+            PropValue {
+                type_,
+                val,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// A level 90 Hu Tao wielding a Staff of Homa and wearing one artifact,
+    /// along with a stack of Hero's Wit.
+    pub fn player_data() -> PlayerData {
+        let mut player_data = PlayerData::new(crate::monitor::embedded_game_data().unwrap());
+
+        player_data.process_characters(&[AvatarInfo {
+            avatar_id: HU_TAO,
+            avatar_type: 1,
+            guid: 100,
+            prop_map: HashMap::from([prop(4001, 90), prop(1002, 6)]),
+            talent_id_list: vec![461],
+            skill_level_map: HashMap::from([(10461, 1), (10462, 9), (10463, 10)]),
+            equip_guid_list: vec![1, 2],
+            ..Default::default()
+        }]);
+
+        let mut artifact = Item {
+            item_id: 81544,
+            guid: 1,
+            ..Default::default()
+        };
+        let mut equip = Equip {
+            is_locked: true,
+            ..Default::default()
+        };
+        equip.set_reliquary(Reliquary {
+            level: 21,
+            main_prop_id: 15008,
+            append_prop_id_list: vec![501204, 501224, 501234, 501244, 501204],
+            ..Default::default()
+        });
+        artifact.set_equip(equip);
+
+        let mut weapon = Item {
+            item_id: STAFF_OF_HOMA,
+            guid: 2,
+            ..Default::default()
+        };
+        let mut equip = Equip::default();
+        equip.set_weapon(Weapon {
+            level: 90,
+            promote_level: 6,
+            affix_map: HashMap::from([(113501, 0)]),
+            ..Default::default()
+        });
+        weapon.set_equip(equip);
+
+        let mut material = Item {
+            item_id: HEROS_WIT,
+            guid: 3,
+            ..Default::default()
+        };
+        material.set_material(Material {
+            count: 12,
+            ..Default::default()
+        });
+
+        player_data.process_items(&[artifact, weapon, material]);
+        player_data
+    }
+}
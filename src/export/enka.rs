@@ -0,0 +1,261 @@
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use serde::Serialize;
+
+use super::{Export, Exporter};
+use crate::player_data::{AvatarInfo, ExportSettings, Item, PlayerData};
+
+/// Character data in the shape of the Enka.Network API's `avatarInfoList`,
+/// which tools like Akasha build their character cards from.
+///
+/// Everything is exported as game ids.  The `flat` fields Enka adds with
+/// names and computed stats are left out as consumers look those up from
+/// the ids themselves.
+pub struct Enka;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct EnkaData {
+    avatar_info_list: Vec<EnkaAvatar>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct EnkaAvatar {
+    avatar_id: u32,
+    prop_map: BTreeMap<u32, EnkaProp>,
+    talent_id_list: Vec<u32>,
+    skill_level_map: BTreeMap<u32, u32>,
+    equip_list: Vec<EnkaEquip>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct EnkaProp {
+    #[serde(rename = "type")]
+    ty: u32,
+    ival: String,
+    val: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct EnkaEquip {
+    item_id: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reliquary: Option<EnkaReliquary>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    weapon: Option<EnkaWeapon>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct EnkaReliquary {
+    level: u32,
+    main_prop_id: u32,
+    append_prop_id_list: Vec<u32>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct EnkaWeapon {
+    level: u32,
+    promote_level: u32,
+    affix_map: BTreeMap<u32, u32>,
+}
+
+impl Exporter for Enka {
+    fn name(&self) -> &'static str {
+        "Enka.Network"
+    }
+
+    fn file_prefix(&self) -> &'static str {
+        "enka_characters"
+    }
+
+    fn export(&self, player_data: &PlayerData, settings: &ExportSettings) -> Result<Export> {
+        let avatar_info_list = if settings.include_characters {
+            player_data
+                .characters()
+                .iter()
+                .filter(|character| character.avatar_type == 1)
+                .filter_map(|character| avatar(player_data, settings, character))
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        let json = serde_json::to_string(&EnkaData { avatar_info_list })?;
+        tracing::trace!("{json}");
+        Ok(Export::Text(json))
+    }
+}
+
+fn avatar(
+    player_data: &PlayerData,
+    settings: &ExportSettings,
+    character: &AvatarInfo,
+) -> Option<EnkaAvatar> {
+    let level = character.prop_map.get(&4001).map(|prop| prop.val as u32)?;
+    let ascension = character
+        .prop_map
+        .get(&1002)
+        .map_or(0, |prop| prop.val as u32);
+    let constellation = character.talent_id_list.len() as u32;
+    if level < settings.min_character_level
+        || ascension < settings.min_character_ascension
+        || constellation < settings.min_character_constellation
+    {
+        return None;
+    }
+
+    let items: Vec<&Item> = character
+        .equip_guid_list
+        .iter()
+        .filter_map(|guid| player_data.items().iter().find(|item| item.guid == *guid))
+        .filter(|item| item.has_equip())
+        .collect();
+
+    // Enka lists artifacts first and the weapon last.
+    let artifacts = items
+        .iter()
+        .filter(|item| settings.include_artifacts && item.equip().has_reliquary())
+        .filter_map(|item| {
+            let reliquary = item.equip().reliquary();
+            let rarity = player_data
+                .game_data()
+                .get_artifact(item.item_id)
+                .map_or(0, |artifact| artifact.rarity);
+            if reliquary.level - 1 < settings.min_artifact_level
+                || rarity < settings.min_artifact_rarity
+            {
+                return None;
+            }
+            Some(EnkaEquip {
+                item_id: item.item_id,
+                reliquary: Some(EnkaReliquary {
+                    level: reliquary.level,
+                    main_prop_id: reliquary.main_prop_id,
+                    append_prop_id_list: reliquary.append_prop_id_list.clone(),
+                }),
+                weapon: None,
+            })
+        });
+    let weapons = items
+        .iter()
+        .filter(|item| settings.include_weapons && item.equip().has_weapon())
+        .filter_map(|item| {
+            let weapon = item.equip().weapon();
+            let rarity = player_data
+                .game_data()
+                .get_weapon(item.item_id)
+                .map_or(0, |weapon| weapon.rarity);
+            let refinement = weapon
+                .affix_map
+                .values()
+                .next()
+                .copied()
+                .unwrap_or_default()
+                + 1;
+            if weapon.level < settings.min_weapon_level
+                || refinement < settings.min_weapon_refinement
+                || weapon.promote_level < settings.min_weapon_ascension
+                || rarity < settings.min_weapon_rarity
+            {
+                return None;
+            }
+            Some(EnkaEquip {
+                item_id: item.item_id,
+                reliquary: None,
+                weapon: Some(EnkaWeapon {
+                    level: weapon.level,
+                    promote_level: weapon.promote_level,
+                    affix_map: weapon.affix_map.iter().map(|(k, v)| (*k, *v)).collect(),
+                }),
+            })
+        });
+
+    Some(EnkaAvatar {
+        avatar_id: character.avatar_id,
+        prop_map: character
+            .prop_map
+            .iter()
+            .map(|(ty, prop)| {
+                let val = prop.val.to_string();
+                (
+                    *ty,
+                    EnkaProp {
+                        ty: *ty,
+                        ival: val.clone(),
+                        val,
+                    },
+                )
+            })
+            .collect(),
+        talent_id_list: character.talent_id_list.clone(),
+        skill_level_map: character
+            .skill_level_map
+            .iter()
+            .map(|(id, level)| (*id, *level))
+            .collect(),
+        equip_list: artifacts.chain(weapons).collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Value, json};
+
+    use super::*;
+    use crate::export::fixtures;
+
+    fn export(settings: &ExportSettings) -> Value {
+        let json = fixtures::text(Enka.export(&fixtures::player_data(), settings));
+        serde_json::from_str(&json).unwrap()
+    }
+
+    #[test]
+    fn characters_are_exported_with_their_equipment() {
+        let export = export(&fixtures::settings());
+        let avatar = &export["avatarInfoList"][0];
+
+        assert_eq!(avatar["avatarId"], fixtures::HU_TAO);
+        assert_eq!(
+            avatar["propMap"]["4001"],
+            json!({"type": 4001, "ival": "90", "val": "90"})
+        );
+        assert_eq!(avatar["talentIdList"], json!([461]));
+        assert_eq!(avatar["skillLevelMap"]["10462"], 9);
+
+        let equip_list = avatar["equipList"].as_array().unwrap();
+        assert_eq!(equip_list.len(), 2);
+        assert_eq!(equip_list[0]["reliquary"]["level"], 21);
+        assert_eq!(
+            equip_list[0]["reliquary"]["appendPropIdList"],
+            json!([501204, 501224, 501234, 501244, 501204])
+        );
+        assert_eq!(equip_list[1]["itemId"], fixtures::STAFF_OF_HOMA);
+        assert_eq!(
+            equip_list[1]["weapon"],
+            json!({"level": 90, "promoteLevel": 6, "affixMap": {"113501": 0}})
+        );
+    }
+
+    #[test]
+    fn export_settings_filter_characters_and_equipment() {
+        let settings = ExportSettings {
+            include_weapons: false,
+            ..fixtures::settings()
+        };
+        let equip_list = &export(&settings)["avatarInfoList"][0]["equipList"];
+        assert_eq!(equip_list.as_array().unwrap().len(), 1);
+        assert!(equip_list[0].get("weapon").is_none());
+
+        let settings = ExportSettings {
+            min_character_constellation: 2,
+            ..fixtures::settings()
+        };
+        assert_eq!(export(&settings)["avatarInfoList"], json!([]));
+    }
+}
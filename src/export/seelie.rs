@@ -0,0 +1,92 @@
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use serde::Serialize;
+
+use super::{Export, Exporter};
+use crate::player_data::{ExportSettings, PlayerData};
+
+/// Material inventory in the shape of a Seelie.me planner backup, which the
+/// planner merges into its inventory on import.
+pub struct Seelie;
+
+#[derive(Debug, Serialize)]
+struct SeelieData {
+    inventory: BTreeMap<String, u32>,
+}
+
+/// Seelie keys materials by the snake case of their English name, dropping
+/// apostrophes and other punctuation (e.g. `heros_wit`).
+fn to_seelie_key(name: &str) -> String {
+    name.split(|c: char| !c.is_alphanumeric() && c != '\'')
+        .map(|word| word.replace('\'', "").to_lowercase())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("_")
+}
+
+impl Exporter for Seelie {
+    fn name(&self) -> &'static str {
+        "Seelie.me"
+    }
+
+    fn file_prefix(&self) -> &'static str {
+        "seelie_inventory"
+    }
+
+    fn export(&self, player_data: &PlayerData, settings: &ExportSettings) -> Result<Export> {
+        let mut inventory = BTreeMap::new();
+        if settings.include_materials {
+            for item in player_data.items() {
+                if !item.has_material() {
+                    continue;
+                }
+                let Ok(name) = player_data.game_data().get_material(item.item_id) else {
+                    continue;
+                };
+                *inventory.entry(to_seelie_key(name)).or_default() += item.material().count;
+            }
+        }
+
+        let json = serde_json::to_string(&SeelieData { inventory })?;
+        tracing::trace!("{json}");
+        Ok(Export::Text(json))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Value, json};
+
+    use super::*;
+    use crate::export::fixtures;
+
+    #[test]
+    fn material_names_become_snake_case_keys() {
+        assert_eq!(to_seelie_key("Hero's Wit"), "heros_wit");
+        assert_eq!(
+            to_seelie_key("Teachings of \"Freedom\""),
+            "teachings_of_freedom"
+        );
+        assert_eq!(to_seelie_key("Dvalin's Plume"), "dvalins_plume");
+    }
+
+    #[test]
+    fn materials_are_exported_as_inventory() {
+        let json = fixtures::text(Seelie.export(&fixtures::player_data(), &fixtures::settings()));
+        let export: Value = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(export, json!({"inventory": {"heros_wit": 12}}));
+    }
+
+    #[test]
+    fn materials_can_be_excluded() {
+        let settings = ExportSettings {
+            include_materials: false,
+            ..fixtures::settings()
+        };
+        let json = fixtures::text(Seelie.export(&fixtures::player_data(), &settings));
+
+        assert_eq!(json, r#"{"inventory":{}}"#);
+    }
+}
@@ -0,0 +1,181 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use anyhow::{Context, Result};
+use base64::prelude::*;
+
+/// File in the data dir whose keys are merged over the built-in ones.
+pub const KEYS_FILE: &str = "keys.json";
+
+/// Every command starts with this magic, which is XORed with the first two
+/// bytes of the key, so the two together give away the key's id.
+const COMMAND_HEADER_MAGIC: u16 = 0x4567;
+
+/// Size of the header of each KCP segment, including the game's extra token.
+const KCP_HEADER_LEN: usize = 28;
+const KCP_CMD_PUSH: u8 = 81;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum KeySource {
+    BuiltIn,
+    StorageDir,
+    File,
+}
+
+impl Display for KeySource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeySource::BuiltIn => write!(f, "built-in"),
+            KeySource::StorageDir => write!(f, "storage dir"),
+            KeySource::File => write!(f, "--keys file"),
+        }
+    }
+}
+
+/// The initial keys the sniffer tries, keyed by id, along with where each
+/// one was loaded from.
+#[derive(Debug, Default)]
+pub struct Keys {
+    pub keys: HashMap<u16, Vec<u8>>,
+    pub sources: HashMap<u16, KeySource>,
+}
+
+impl Keys {
+    /// Load the built-in keys and merge the keys in the data dir and then
+    /// `keys_path` over them.  Later keys replace earlier ones with the same id.
+    ///
+    /// Extra key files that fail to load are logged and skipped so a bad file
+    /// doesn't stop the built-in keys from being used.
+    pub fn load(keys_path: Option<&Path>) -> Result<Self> {
+        let mut keys = Keys::default();
+        keys.merge(
+            parse_keys(include_bytes!("../keys/gi.json"))?,
+            KeySource::BuiltIn,
+        );
+
+        let extra_keys = storage_keys_path()
+            .filter(|path| path.exists())
+            .map(|path| (path, KeySource::StorageDir))
+            .into_iter()
+            .chain(keys_path.map(|path| (path.to_path_buf(), KeySource::File)));
+        for (path, source) in extra_keys {
+            match read_keys(&path) {
+                Ok(extra_keys) => {
+                    tracing::info!("Loaded {} keys from {}", extra_keys.len(), path.display());
+                    keys.merge(extra_keys, source);
+                }
+                Err(e) => tracing::error!("Unable to load keys: {e:#}"),
+            }
+        }
+
+        Ok(keys)
+    }
+
+    fn merge(&mut self, keys: HashMap<u16, Vec<u8>>, source: KeySource) {
+        for (id, key) in keys {
+            if self.keys.insert(id, key).is_some() {
+                tracing::info!("Key {id} from the {source} replaces an earlier key");
+            }
+            self.sources.insert(id, source);
+        }
+    }
+}
+
+fn read_keys(path: &Path) -> Result<HashMap<u16, Vec<u8>>> {
+    let json = std::fs::read(path).with_context(|| format!("Unable to read {path:?}"))?;
+    parse_keys(&json).with_context(|| format!("Unable to parse {path:?}"))
+}
+
+/// Parse a map of key ids to base64 encoded keys, in the same format as
+/// `keys/gi.json`.
+fn parse_keys(json: &[u8]) -> Result<HashMap<u16, Vec<u8>>> {
+    let keys: HashMap<u16, String> = serde_json::from_slice(json)?;
+
+    keys.iter()
+        .map(|(key, value)| -> Result<_, _> { Ok((*key, BASE64_STANDARD.decode(value)?)) })
+        .collect::<Result<HashMap<_, _>>>()
+}
+
+/// Work out the id of the key a session's first command was encrypted with
+/// from the UDP payload carrying it.
+///
+/// Returns `None` for payloads that aren't a KCP segment with data, like the
+/// handshake and acks.
+pub fn session_key_id(payload: &[u8]) -> Option<u16> {
+    if payload.len() < KCP_HEADER_LEN + 2 || payload[8] != KCP_CMD_PUSH {
+        return None;
+    }
+    let data = &payload[KCP_HEADER_LEN..];
+    Some(u16::from_be_bytes([data[0], data[1]]) ^ COMMAND_HEADER_MAGIC)
+}
+
+/// The key id each session of the current run used, for diagnostics.
+#[derive(Clone, Debug)]
+pub struct SessionKey {
+    pub key_id: u16,
+    /// Where the key was loaded from, or `None` when there is no key with
+    /// this id.
+    pub source: Option<KeySource>,
+    /// When the session's first packet was captured.
+    pub started: SystemTime,
+    pub client: SocketAddr,
+    pub server: SocketAddr,
+    pub interface: Option<Arc<str>>,
+}
+
+impl Display for SessionKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.source {
+            Some(source) => write!(f, "key {} ({source})", self.key_id),
+            None => write!(f, "key {} (missing)", self.key_id),
+        }
+    }
+}
+
+/// Keys in this file are picked up at startup without needing `--keys`.
+pub fn storage_keys_path() -> Option<PathBuf> {
+    crate::data_dir().map(|dir| dir.join(KEYS_FILE))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_ids_match_the_first_bytes_of_their_keys() {
+        let keys = parse_keys(include_bytes!("../keys/gi.json")).unwrap();
+        for (id, key) in keys {
+            assert_eq!(id, u16::from_be_bytes([key[0], key[1]]));
+        }
+    }
+
+    #[test]
+    fn later_keys_replace_earlier_ones() {
+        let mut keys = Keys::default();
+        keys.merge(
+            HashMap::from([(1, vec![1]), (2, vec![2])]),
+            KeySource::BuiltIn,
+        );
+        keys.merge(HashMap::from([(2, vec![3])]), KeySource::File);
+
+        assert_eq!(keys.keys[&1], [1]);
+        assert_eq!(keys.keys[&2], [3]);
+        assert_eq!(keys.sources[&2], KeySource::File);
+    }
+
+    #[test]
+    fn session_key_id_is_read_from_the_first_command() {
+        let mut payload = vec![0; KCP_HEADER_LEN];
+        payload[8] = KCP_CMD_PUSH;
+        // First two bytes of key 13980 (0x369c) XORed with the magic.
+        payload.extend_from_slice(&(0x369c_u16 ^ 0x4567).to_be_bytes());
+        payload.extend_from_slice(&[0; 10]);
+
+        assert_eq!(session_key_id(&payload), Some(13980));
+        assert_eq!(session_key_id(&payload[..20]), None);
+    }
+}
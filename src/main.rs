@@ -1,7 +1,9 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")] // hide console window on Windows in release
 
+use std::collections::HashMap;
 use std::fmt::Display;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 use std::time::Instant;
 
 use anyhow::{Context, Result};
@@ -12,19 +14,36 @@ use tracing_appender::rolling::Rotation;
 use tracing_subscriber::prelude::*;
 use tracing_subscriber::{EnvFilter, reload};
 
-use crate::player_data::ExportSettings;
+use crate::artifacts::{ArtifactAnalysis, CleanupRecommendation, ScoreSettings};
+use crate::capture::{BackendType, CaptureSettings, PortSet};
+use crate::export::{Export, ExportFormat};
+use crate::keys::SessionKey;
+use crate::planner::{Goal, Plan};
+use crate::player_data::{ExportSettings, UnknownIdReport};
 
 mod admin;
 mod app;
+mod artifacts;
 mod capture;
+mod export;
 mod good;
+mod keys;
 mod monitor;
+mod planner;
 mod player_data;
+mod spreadsheet;
 mod update;
 mod wish;
 
 const APP_ID: &str = "Irminsul";
 
+/// Irminsul runs portable, keeping its data in `PORTABLE_DATA_DIR` next to
+/// the executable, when this file sits next to the executable.
+const PORTABLE_MARKER: &str = "portable.txt";
+const PORTABLE_DATA_DIR: &str = "data";
+
+static DATA_DIR: OnceLock<Option<PathBuf>> = OnceLock::new();
+
 #[derive(Clone, Copy, Debug)]
 pub enum ConfirmationType {
     Initial,
@@ -35,8 +54,12 @@ pub enum ConfirmationType {
 pub enum State {
     Starting,
     CheckingForUpdate,
-    WaitingForUpdateConfirmation(String),
-    Updating,
+    WaitingForUpdateConfirmation {
+        version: String,
+        /// Release notes, in markdown.
+        notes: Option<String>,
+    },
+    Updating(UpdateProgress),
     /// `needs_caps` is set when the update dropped the packet capture
     /// permissions the user had granted, and they need to be re-granted.
     Updated {
@@ -48,14 +71,110 @@ pub enum State {
     Main,
 }
 
+/// Progress of downloading an update.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct UpdateProgress {
+    downloaded: u64,
+    /// Size of the download, when the server gave it.
+    total: Option<u64>,
+    attempt: u32,
+    /// Why the previous attempt failed, until the retry gets going.
+    error: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SessionEnd {
+    Timeout,
+    Disconnected,
+}
+
+/// Progress of the game session being captured.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SessionState {
+    WaitingForTraffic,
+    /// Game traffic from a session whose handshake happened before the
+    /// capture started, so it can never be decrypted.
+    MissedHandshake,
+    HandshakeSeen,
+    KeysEstablished,
+    PlayerDataReceived,
+    Ended(SessionEnd),
+}
+
+impl SessionState {
+    /// Whether the game is connected, as far as the capture can tell.
+    pub fn is_active(&self) -> bool {
+        matches!(
+            self,
+            SessionState::MissedHandshake
+                | SessionState::HandshakeSeen
+                | SessionState::KeysEstablished
+                | SessionState::PlayerDataReceived
+        )
+    }
+}
+
+impl Display for SessionState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SessionState::WaitingForTraffic => write!(f, "Waiting for game traffic"),
+            SessionState::MissedHandshake => write!(
+                f,
+                "Game session already in progress — relog or re-enter the door"
+            ),
+            SessionState::HandshakeSeen => write!(f, "Connecting to game server"),
+            SessionState::KeysEstablished => write!(f, "Waiting for player data"),
+            SessionState::PlayerDataReceived => write!(f, "Player data received"),
+            SessionState::Ended(SessionEnd::Timeout) => write!(f, "Session timed out"),
+            SessionState::Ended(SessionEnd::Disconnected) => write!(f, "Session ended"),
+        }
+    }
+}
+
+/// Whether the running capture is working as asked.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum CaptureHealth {
+    Running,
+    /// Capture stopped with `error` and is being restarted.
+    Restarting {
+        error: String,
+        attempt: u32,
+    },
+    /// Capturing with a fallback because the chosen backend couldn't be
+    /// started.
+    FallenBack(BackendType),
+}
+
+impl Display for CaptureHealth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CaptureHealth::Running => write!(f, "Capturing"),
+            CaptureHealth::Restarting { attempt, .. } => {
+                write!(f, "Capture interrupted, restarting (attempt {attempt})")
+            }
+            CaptureHealth::FallenBack(backend) => {
+                write!(f, "Capturing with the {backend:?} fallback")
+            }
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum Message {
     UpdateAcknowledged,
     UpdateCanceled,
     DownloadAcknowledged,
-    StartCapture,
+    DownloadSkipped,
+    StartCapture(CaptureSettings),
     StopCapture,
-    ExportGenshinOptimizer(ExportSettings, oneshot::Sender<Result<String>>),
+    Export(
+        ExportFormat,
+        ExportSettings,
+        oneshot::Sender<Result<Export>>,
+    ),
+    Plan(HashMap<String, Goal>, oneshot::Sender<Plan>),
+    AnalyzeArtifacts(ScoreSettings, oneshot::Sender<Vec<ArtifactAnalysis>>),
+    RecommendCleanup(ScoreSettings, oneshot::Sender<Vec<CleanupRecommendation>>),
 }
 
 #[derive(Clone, Debug)]
@@ -63,6 +182,9 @@ pub struct DataUpdated {
     achievements_updated: Option<Instant>,
     characters_updated: Option<Instant>,
     items_updated: Option<Instant>,
+    unknown_ids_updated: Option<Instant>,
+    capture_errors_updated: Option<Instant>,
+    game_data_error_updated: Option<Instant>,
 }
 
 impl DataUpdated {
@@ -71,6 +193,9 @@ impl DataUpdated {
             achievements_updated: None,
             characters_updated: None,
             items_updated: None,
+            unknown_ids_updated: None,
+            capture_errors_updated: None,
+            game_data_error_updated: None,
         }
     }
 }
@@ -85,7 +210,21 @@ impl Default for DataUpdated {
 pub struct AppState {
     state: State,
     capturing: bool,
+    capture_health: CaptureHealth,
+    session: SessionState,
     updated: DataUpdated,
+    /// Ids in the current capture that the game data doesn't know about.
+    unknown_ids: UnknownIdReport,
+    /// Problems starting the last capture.
+    capture_errors: Vec<String>,
+    /// Why the game data given with `--game-data` or in power tools couldn't
+    /// be loaded.
+    game_data_error: Option<String>,
+    /// Packets the current capture dropped because they couldn't be
+    /// processed fast enough.
+    dropped_packets: u64,
+    /// Key ids used by the sessions seen since Irminsul started.
+    session_keys: Vec<SessionKey>,
 }
 
 impl AppState {
@@ -93,7 +232,14 @@ impl AppState {
         AppState {
             state: State::Starting,
             capturing: false,
+            capture_health: CaptureHealth::Running,
+            session: SessionState::WaitingForTraffic,
             updated: DataUpdated::new(),
+            unknown_ids: UnknownIdReport::default(),
+            capture_errors: Vec::new(),
+            game_data_error: None,
+            dropped_packets: 0,
+            session_keys: Vec::new(),
         }
     }
 }
@@ -104,12 +250,70 @@ struct Args {
     #[arg(long, default_value_t = false)]
     no_admin: bool,
 
+    /// Don't check for updates on startup.
+    #[arg(long, default_value_t = false)]
+    no_update: bool,
+
     #[arg(
         long = "capture-backend",
         value_enum,
         default_value_t = capture::DEFAULT_CAPTURE_BACKEND_TYPE
     )]
     capture_backend: capture::BackendType,
+
+    /// UDP ports to capture, as a comma separated list of ports and ranges
+    /// (e.g. `22101-22102,22201`).
+    #[arg(long = "ports", value_name = "PORTS")]
+    ports: Option<PortSet>,
+
+    /// BPF expression for the pcap backend to capture with instead of one
+    /// built from the ports.
+    #[arg(long = "capture-filter", value_name = "EXPRESSION")]
+    capture_filter: Option<String>,
+
+    /// Interface for the pcap backend to capture on, instead of every
+    /// connected one.  Can be given more than once.
+    #[arg(long = "interface", value_name = "NAME")]
+    interfaces: Vec<String>,
+
+    /// Unix socket, or named pipe on Windows, the pipe backend reads a pcap
+    /// stream from.
+    #[arg(long = "capture-pipe", value_name = "PATH")]
+    capture_pipe: Option<String>,
+
+    /// Run as the capture helper, serving the game's traffic to unprivileged
+    /// copies of Irminsul over the systemd socket or `--capture-pipe`,
+    /// instead of starting the UI.
+    #[arg(long, default_value_t = false)]
+    capture_helper: bool,
+
+    /// Exit successfully if Irminsul can capture packets, used to check
+    /// permissions granted from the UI.
+    #[arg(long, default_value_t = false, hide = true)]
+    check_capture_permissions: bool,
+
+    /// Version of Irminsul that failed to start and was replaced by this one.
+    #[arg(long, value_name = "VERSION", hide = true)]
+    rolled_back_from: Option<String>,
+
+    /// Keep logs, settings and downloaded game data in this directory instead
+    /// of the platform's app data directory.
+    #[arg(long = "data-dir", value_name = "PATH")]
+    data_dir: Option<PathBuf>,
+
+    /// List the interfaces the pcap backend can capture on and exit.
+    #[arg(long, default_value_t = false)]
+    list_interfaces: bool,
+
+    /// Load game data from this JSON or gzipped JSON file instead of the
+    /// built-in or downloaded data.
+    #[arg(long = "game-data", value_name = "PATH")]
+    game_data: Option<PathBuf>,
+
+    /// Load extra decryption keys from this JSON file, replacing built-in keys
+    /// with the same id.
+    #[arg(long = "keys", value_name = "PATH")]
+    keys: Option<PathBuf>,
 }
 
 #[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize, Default)]
@@ -161,16 +365,82 @@ impl ReloadHandle {
 }
 
 fn main() -> eframe::Result {
+    let args = Args::parse();
+
+    if args.check_capture_permissions {
+        #[cfg(unix)]
+        std::process::exit(if admin::can_capture() { 0 } else { 1 });
+    }
+
+    let capture_overrides = capture::CaptureOverrides {
+        ports: args.ports,
+        filter: args.capture_filter,
+        interfaces: args.interfaces,
+        pipe: args.capture_pipe,
+    };
+
+    if args.capture_helper {
+        run_capture_helper(capture_overrides.apply(&CaptureSettings::default()));
+    }
+
+    let data_dir = DATA_DIR
+        .get_or_init(|| resolve_data_dir(args.data_dir, std::env::current_exe().ok().as_deref()))
+        .clone();
+
     let (_guard, reload_handle) = tracing_init().unwrap();
+    match &data_dir {
+        Some(dir) => tracing::info!("Data dir: {}", dir.display()),
+        None => tracing::warn!("Data dir not found, settings won't be saved"),
+    }
 
-    let args = Args::parse();
+    if args.list_interfaces {
+        #[cfg(windows)]
+        attach_parent_console();
+        match capture::list_interfaces() {
+            Ok(interfaces) => {
+                for interface in interfaces {
+                    println!("{interface}");
+                }
+            }
+            Err(e) => eprintln!("Unable to list interfaces: {e}"),
+        }
+        return Ok(());
+    }
 
-    if !args.no_admin {
+    #[allow(unused_mut)]
+    let mut capture_backend = args.capture_backend;
+    // Capture through the helper when it is installed rather than asking for
+    // permissions that the next update would drop again.
+    #[cfg(unix)]
+    if capture_backend == capture::BackendType::Pcap
+        && !admin::can_capture()
+        && std::path::Path::new(
+            &capture_overrides
+                .apply(&CaptureSettings::default())
+                .helper_path(),
+        )
+        .exists()
+    {
+        tracing::info!("Capturing through the capture helper");
+        capture_backend = capture::BackendType::Helper;
+    }
+
+    // Backends that get their packets from elsewhere don't need permissions.
+    if !args.no_admin && capture_backend.needs_privileges() {
         #[cfg(any(windows, unix))]
         admin::ensure_admin();
     }
 
-    let capture_backend = args.capture_backend;
+    // Only after elevating, as the process that elevates exits before
+    // showing its window.
+    update::roll_back_failed_update();
+
+    let game_data_path = args.game_data;
+    let keys_path = args.keys;
+    let update_args = update::UpdateArgs {
+        no_update: args.no_update,
+        rolled_back_from: args.rolled_back_from,
+    };
 
     let background_image_size = [1600., 1000.];
 
@@ -185,6 +455,7 @@ fn main() -> eframe::Result {
                     .expect("Failed to load icon"),
             ),
         persist_window: false,
+        persistence_path: data_dir.map(|dir| dir.join("app.ron")),
         ..Default::default()
     };
     eframe::run_native(
@@ -195,13 +466,43 @@ fn main() -> eframe::Result {
                 cc,
                 reload_handle,
                 capture_backend,
+                game_data_path,
+                keys_path,
+                capture_overrides,
+                update_args,
             )))
         }),
     )
 }
 
+/// Where Irminsul keeps its data: `data_dir_arg` when given, the portable
+/// data folder when the portable marker is next to `exe`, and otherwise the
+/// platform's app data directory.
+///
+/// A relative `data_dir_arg` is made absolute, so logs and settings stay put
+/// if the working directory changes.
+fn resolve_data_dir(data_dir_arg: Option<PathBuf>, exe: Option<&Path>) -> Option<PathBuf> {
+    if let Some(dir) = data_dir_arg {
+        return Some(std::path::absolute(&dir).unwrap_or(dir));
+    }
+    if let Some(exe_dir) = exe.and_then(Path::parent)
+        && exe_dir.join(PORTABLE_MARKER).exists()
+    {
+        return Some(exe_dir.join(PORTABLE_DATA_DIR));
+    }
+    eframe::storage_dir(APP_ID)
+}
+
+/// Directory holding logs, packet logs, saved settings and downloaded game
+/// data.
+fn data_dir() -> Option<PathBuf> {
+    DATA_DIR
+        .get_or_init(|| resolve_data_dir(None, std::env::current_exe().ok().as_deref()))
+        .clone()
+}
+
 fn log_dir() -> Result<PathBuf> {
-    let mut dir = eframe::storage_dir(APP_ID).context("Storage dir not found")?;
+    let mut dir = data_dir().context("Data dir not found")?;
     dir.push("log");
     println!("Log folder: {}", dir.display());
     Ok(dir)
@@ -213,6 +514,40 @@ fn open_log_dir() -> Result<()> {
     Ok(())
 }
 
+/// Print to the console Irminsul was started from.  Release builds on Windows
+/// don't get a console of their own, so anything printed is otherwise lost.
+#[cfg(windows)]
+fn attach_parent_console() {
+    use windows::Win32::System::Console::{ATTACH_PARENT_PROCESS, AttachConsole};
+
+    // Fails when started without a console, e.g. from Explorer, or when
+    // debug builds already have one.
+    let _ = unsafe { AttachConsole(ATTACH_PARENT_PROCESS) };
+}
+
+/// Run as the capture helper and exit.  It runs headless as a service, so it
+/// logs to stderr for the journal instead of to the log dir.
+fn run_capture_helper(settings: CaptureSettings) -> ! {
+    #[cfg(all(unix, feature = "pcap"))]
+    {
+        tracing_subscriber::fmt()
+            .with_env_filter(EnvFilter::new("info"))
+            .with_writer(std::io::stderr)
+            .init();
+        if let Err(e) = capture::helper::run_helper(settings) {
+            tracing::error!("Capture helper failed: {e:#}");
+            std::process::exit(1);
+        }
+        std::process::exit(0);
+    }
+    #[cfg(not(all(unix, feature = "pcap")))]
+    {
+        let _ = settings;
+        eprintln!("The capture helper is not supported on this build");
+        std::process::exit(1);
+    }
+}
+
 fn tracing_init() -> Result<(tracing_appender::non_blocking::WorkerGuard, ReloadHandle)> {
     let appender = tracing_appender::rolling::Builder::new()
         .filename_prefix("log")
@@ -234,3 +569,43 @@ fn tracing_init() -> Result<(tracing_appender::non_blocking::WorkerGuard, Reload
 
     Ok((guard, ReloadHandle(reload_handle)))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_data_dir_arg_wins_over_the_portable_marker() {
+        let dir = tempfile::tempdir().unwrap();
+        let exe = dir.path().join("irminsul.exe");
+        std::fs::write(dir.path().join(PORTABLE_MARKER), "").unwrap();
+        let arg = dir.path().join("elsewhere");
+
+        assert_eq!(resolve_data_dir(Some(arg.clone()), Some(&exe)), Some(arg));
+    }
+
+    #[test]
+    fn a_relative_data_dir_arg_is_made_absolute() {
+        let data_dir = resolve_data_dir(Some(PathBuf::from("data")), None).unwrap();
+
+        assert!(data_dir.is_absolute());
+        assert_eq!(data_dir, std::env::current_dir().unwrap().join("data"));
+    }
+
+    #[test]
+    fn the_portable_marker_wins_over_the_platform_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let exe = dir.path().join("irminsul.exe");
+
+        assert_eq!(
+            resolve_data_dir(None, Some(&exe)),
+            eframe::storage_dir(APP_ID)
+        );
+
+        std::fs::write(dir.path().join(PORTABLE_MARKER), "").unwrap();
+        assert_eq!(
+            resolve_data_dir(None, Some(&exe)),
+            Some(dir.path().join(PORTABLE_DATA_DIR))
+        );
+    }
+}
@@ -1,23 +1,59 @@
 use std::collections::HashMap;
-use std::fs;
-use std::io::{BufWriter, Write};
-use std::time::Instant;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime};
 
 use anime_game_data::AnimeGameData;
-use anyhow::{Context, Result, anyhow};
+use anyhow::{Context, Result};
 use auto_artifactarium::{
-    GameCommand, GamePacket, GameSniffer, matches_achievement_packet, matches_avatar_packet,
-    matches_item_packet,
+    ConnectionPacket, GameCommand, GamePacket, GameSniffer, matches_achievement_packet,
+    matches_avatar_packet, matches_item_packet,
 };
-use base64::prelude::*;
 use chrono::prelude::*;
+use flate2::Compression;
 use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use tokio::sync::mpsc::error::TrySendError;
 use tokio::sync::{mpsc, watch};
 use tokio_util::sync::CancellationToken;
 
-use crate::capture::{BackendType, create_capture};
-use crate::player_data::PlayerData;
-use crate::{APP_ID, AppState, DataUpdated, Message, State};
+use crate::capture::{
+    BackendType, CaptureBackend, CaptureError, CaptureSettings, PACKET_QUEUE_LEN, Packet,
+    create_capture_with_fallback,
+};
+use crate::keys::{self, KeySource, Keys, SessionKey};
+use crate::player_data::{PlayerData, UnknownIdReport};
+use crate::{
+    AppState, CaptureHealth, ConfirmationType, DataUpdated, Message, SessionEnd, SessionState,
+    State,
+};
+
+/// Cache `anime_game_data` keeps of the downloaded data to tell when it is
+/// out of date.
+const GAME_DATA_CACHE_FILE: &str = "game_data.json";
+/// Downloaded game data in the same format as the copy embedded at build time.
+const GAME_DATA_FILE: &str = "game_data.gz";
+const GAME_DATA_CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+/// Touched when the user skips a game data download, so they aren't asked
+/// again until `GAME_DATA_SKIP_DURATION` has passed.
+const GAME_DATA_SKIPPED_FILE: &str = "game_data_skipped";
+const GAME_DATA_SKIP_DURATION: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+/// Undecodable game packets to see before deciding the capture missed the
+/// session's handshake.  The game sends several a second while playing.
+const MISSED_HANDSHAKE_PACKET_THRESHOLD: u32 = 20;
+/// The game keeps sending packets while connected, even when idle, so a
+/// session this quiet has ended without a disconnect being seen.
+const SESSION_TIMEOUT: Duration = Duration::from_secs(60);
+const SESSION_TIMEOUT_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+const DROPPED_PACKETS_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// Delay before the first attempt to restart a capture that stopped, doubled
+/// after each attempt up to the max.
+const RESTART_DELAY_MIN: Duration = Duration::from_secs(1);
+const RESTART_DELAY_MAX: Duration = Duration::from_secs(60);
 
 struct AppStateManager {
     app_state: AppState,
@@ -46,6 +82,60 @@ impl AppStateManager {
         self.app_state.updated = updated;
         let _ = self.state_tx.send(self.app_state.clone());
     }
+
+    pub fn update_session_state(&mut self, session: SessionState) {
+        if self.app_state.session != session {
+            tracing::info!("Session state: {session:?}");
+            self.app_state.session = session;
+            let _ = self.state_tx.send(self.app_state.clone());
+        }
+    }
+
+    pub fn update_capture_errors(&mut self, capture_errors: Vec<String>) {
+        self.app_state.capture_errors = capture_errors;
+        self.app_state.updated.capture_errors_updated = Some(Instant::now());
+        let _ = self.state_tx.send(self.app_state.clone());
+    }
+
+    pub fn update_game_data_error(&mut self, game_data_error: String) {
+        self.app_state.game_data_error = Some(game_data_error);
+        self.app_state.updated.game_data_error_updated = Some(Instant::now());
+        let _ = self.state_tx.send(self.app_state.clone());
+    }
+
+    pub fn update_capture_health(&mut self, capture_health: CaptureHealth) {
+        if self.app_state.capture_health != capture_health {
+            tracing::info!("Capture health: {capture_health:?}");
+            self.app_state.capture_health = capture_health;
+            let _ = self.state_tx.send(self.app_state.clone());
+        }
+    }
+
+    pub fn update_dropped_packets(&mut self, dropped_packets: u64) {
+        if self.app_state.dropped_packets != dropped_packets {
+            self.app_state.dropped_packets = dropped_packets;
+            let _ = self.state_tx.send(self.app_state.clone());
+        }
+    }
+
+    pub fn add_session_key(&mut self, session_key: SessionKey) {
+        self.app_state.session_keys.push(session_key);
+        let _ = self.state_tx.send(self.app_state.clone());
+    }
+
+    pub fn update_unknown_ids(&mut self, unknown_ids: UnknownIdReport) {
+        self.app_state.unknown_ids = unknown_ids;
+        self.app_state.updated.unknown_ids_updated = Some(Instant::now());
+        let _ = self.state_tx.send(self.app_state.clone());
+    }
+}
+
+/// What a capture task reports back to the monitor.
+enum CaptureEvent {
+    /// Capture stopped with `error` and is about to be restarted.
+    Interrupted { error: String, attempt: u32 },
+    /// Capture was restarted with this backend.
+    Restarted(BackendType),
 }
 
 pub struct Monitor {
@@ -53,36 +143,84 @@ pub struct Monitor {
     ui_message_rx: mpsc::UnboundedReceiver<Message>,
     log_packet_rx: watch::Receiver<bool>,
     player_data: PlayerData,
-    sniffer: GameSniffer,
+    keys: HashMap<u16, Vec<u8>>,
+    key_sources: HashMap<u16, KeySource>,
+    /// Sessions being captured, by the client's endpoint.
+    sessions: HashMap<SocketAddr, Session>,
     capture_cancel_token: Option<CancellationToken>,
-    packet_tx: mpsc::UnboundedSender<Vec<u8>>,
-    packet_rx: mpsc::UnboundedReceiver<Vec<u8>>,
+    packet_tx: mpsc::Sender<Packet>,
+    packet_rx: mpsc::Receiver<Packet>,
+    /// Packets the current capture has dropped because the monitor or the
+    /// backend fell behind.
+    dropped_packets: Arc<AtomicU64>,
+    capture_event_tx: mpsc::UnboundedSender<CaptureEvent>,
+    capture_event_rx: mpsc::UnboundedReceiver<CaptureEvent>,
     capture_backend: BackendType,
 }
 
+/// A game session being captured.  Each one gets its own sniffer so that
+/// sessions seen at the same time, or one after another, don't interfere
+/// with each other's decoding.
+struct Session {
+    sniffer: GameSniffer,
+    state: SessionState,
+    started: SystemTime,
+    server: SocketAddr,
+    interface: Option<Arc<str>>,
+    /// Set when the handshake is seen until the key the session's first
+    /// command was encrypted with has been seen.
+    key_pending: bool,
+    /// Packets with data that couldn't be decoded before a handshake.
+    undecodable_packets: u32,
+    last_packet: Instant,
+}
+
+impl Session {
+    fn new(keys: &HashMap<u16, Vec<u8>>, packet: &Packet) -> Self {
+        Self {
+            sniffer: GameSniffer::new().set_initial_keys(keys.clone()),
+            state: SessionState::WaitingForTraffic,
+            started: packet.timestamp,
+            server: packet.server(),
+            interface: packet.interface.clone(),
+            key_pending: false,
+            undecodable_packets: 0,
+            last_packet: Instant::now(),
+        }
+    }
+}
+
 impl Monitor {
     pub async fn new(
         state_tx: watch::Sender<AppState>,
         mut ui_message_rx: mpsc::UnboundedReceiver<Message>,
         log_packet_rx: watch::Receiver<bool>,
         capture_backend: BackendType,
+        game_data_path: Option<PathBuf>,
+        keys_path: Option<PathBuf>,
     ) -> Result<Self> {
         let mut app_state = AppStateManager::new(state_tx.borrow().clone(), state_tx.clone());
-        let game_data = get_database(&mut app_state, &mut ui_message_rx).await?;
+        let game_data = get_database(&mut app_state, &mut ui_message_rx, game_data_path).await?;
         let player_data = PlayerData::new(game_data);
-        let keys = load_keys()?;
-        let sniffer = GameSniffer::new().set_initial_keys(keys);
-        let (packet_tx, packet_rx) = mpsc::unbounded_channel();
+        let keys = Keys::load(keys_path.as_deref())?;
+        tracing::info!("Loaded {} decryption keys", keys.keys.len());
+        let (packet_tx, packet_rx) = mpsc::channel(PACKET_QUEUE_LEN);
+        let (capture_event_tx, capture_event_rx) = mpsc::unbounded_channel();
 
         Ok(Self {
             app_state,
             player_data,
             ui_message_rx,
             log_packet_rx,
-            sniffer,
+            keys: keys.keys,
+            key_sources: keys.sources,
+            sessions: HashMap::new(),
             capture_cancel_token: None,
             packet_tx,
             packet_rx,
+            dropped_packets: Arc::new(AtomicU64::new(0)),
+            capture_event_tx,
+            capture_event_rx,
             capture_backend,
         })
     }
@@ -90,31 +228,70 @@ impl Monitor {
     pub async fn run(mut self) {
         self.app_state.update_app_state(State::Main);
 
+        let mut session_timeout_check = tokio::time::interval(SESSION_TIMEOUT_CHECK_INTERVAL);
+        let mut dropped_packets_check = tokio::time::interval(DROPPED_PACKETS_CHECK_INTERVAL);
         loop {
             #[rustfmt::skip]
                 tokio::select! {
                     Some(packet) = self.packet_rx.recv() => self.handle_packet(packet),
                     Some(msg) = self.ui_message_rx.recv() => self.handle_ui_msg(msg),
+                    Some(event) = self.capture_event_rx.recv() => self.handle_capture_event(event),
+                    _ = session_timeout_check.tick() => self.check_session_timeout(),
+                    _ = dropped_packets_check.tick() => self.check_dropped_packets(),
                 }
         }
     }
 
     fn handle_ui_msg(&mut self, msg: Message) {
         match msg {
-            Message::StartCapture => {
+            Message::StartCapture(settings) => {
                 if self.capture_cancel_token.is_some() {
                     tracing::warn!("Capture start request with an existing cancel token");
                 }
 
+                let (backend, capture) =
+                    match create_capture_with_fallback(self.capture_backend, &settings) {
+                        Ok(capture) => capture,
+                        Err(e) => {
+                            let error = format!(
+                                "Error creating packet capture using {:?}: {e}",
+                                self.capture_backend
+                            );
+                            tracing::error!("{error}");
+                            self.app_state.update_capture_errors(vec![error]);
+                            return;
+                        }
+                    };
+                let interface_failures = capture
+                    .interface_failures()
+                    .iter()
+                    .map(|failure| format!("Unable to capture on {failure}"))
+                    .collect::<Vec<_>>();
+                if !interface_failures.is_empty() {
+                    self.app_state.update_capture_errors(interface_failures);
+                }
+
                 // Spawn capture task.
                 let cancel_token = CancellationToken::new();
+                self.dropped_packets = Arc::new(AtomicU64::new(0));
                 tokio::spawn(capture_task(
                     cancel_token.clone(),
                     self.packet_tx.clone(),
+                    self.capture_event_tx.clone(),
                     self.capture_backend,
+                    settings,
+                    capture,
+                    self.dropped_packets.clone(),
                 ));
                 self.capture_cancel_token = Some(cancel_token);
+                self.app_state
+                    .update_capture_health(self.running_health(backend));
                 self.app_state.update_capturing_state(true);
+                self.app_state.app_state.unknown_ids = UnknownIdReport::default();
+                self.sessions.clear();
+                self.app_state.update_dropped_packets(0);
+                self.app_state
+                    .update_session_state(SessionState::WaitingForTraffic);
             }
             Message::StopCapture => {
                 let Some(cancel_token) = self.capture_cancel_token.take() else {
@@ -124,22 +301,136 @@ impl Monitor {
                 cancel_token.cancel();
                 self.app_state.update_capturing_state(false);
             }
-            Message::ExportGenshinOptimizer(settings, reply_tx) => {
-                let _ = reply_tx.send(self.player_data.export_genshin_optimizer(&settings));
+            Message::Export(format, settings, reply_tx) => {
+                let _ = reply_tx.send(format.exporter().export(&self.player_data, &settings));
+            }
+            Message::Plan(goals, reply_tx) => {
+                let _ = reply_tx.send(self.player_data.plan(&goals));
+            }
+            Message::AnalyzeArtifacts(settings, reply_tx) => {
+                let _ = reply_tx.send(self.player_data.analyze_artifacts(&settings));
+            }
+            Message::RecommendCleanup(settings, reply_tx) => {
+                let _ = reply_tx.send(self.player_data.recommend_artifact_cleanup(&settings));
             }
             _ => (),
         }
     }
 
-    fn handle_packet(&mut self, packet: Vec<u8>) {
-        let Some(GamePacket::Commands(commands)) = self.sniffer.receive_packet(packet) else {
+    fn handle_capture_event(&mut self, event: CaptureEvent) {
+        // Events from a capture that has since been stopped are stale.
+        if self.capture_cancel_token.is_none() {
             return;
+        }
+        let health = match event {
+            CaptureEvent::Interrupted { error, attempt } => {
+                CaptureHealth::Restarting { error, attempt }
+            }
+            CaptureEvent::Restarted(backend) => self.running_health(backend),
+        };
+        self.app_state.update_capture_health(health);
+    }
+
+    fn running_health(&self, backend: BackendType) -> CaptureHealth {
+        if backend == self.capture_backend {
+            CaptureHealth::Running
+        } else {
+            CaptureHealth::FallenBack(backend)
+        }
+    }
+
+    fn handle_packet(&mut self, packet: Packet) {
+        let client = packet.client();
+        let keys = &self.keys;
+        let session = self.sessions.entry(client).or_insert_with(|| {
+            tracing::info!(
+                "New game session between {client} and {} on {}",
+                packet.server(),
+                packet.interface.as_deref().unwrap_or("unknown interface")
+            );
+            Session::new(keys, &packet)
+        });
+        session.last_packet = Instant::now();
+        let previous_state = session.state;
+        tracing::trace!(
+            "{:?} packet of {} bytes for session {client}",
+            packet.direction,
+            packet.datagram.payload.len()
+        );
+
+        // Id of the key the segment's data would be encrypted with if it's the
+        // first command of a session.  Only segments carrying data have one.
+        let segment_key_id = keys::session_key_id(&packet.datagram.payload);
+        let mut session_key_id = None;
+        if session.key_pending && segment_key_id.is_some() {
+            session.key_pending = false;
+            session_key_id = segment_key_id;
+        }
+
+        let commands = match session
+            .sniffer
+            .receive_packet(packet.datagram.to_ethernet_frame())
+        {
+            Some(GamePacket::Commands(commands)) if !commands.is_empty() => {
+                if session.state == SessionState::HandshakeSeen {
+                    session.state = SessionState::KeysEstablished;
+                }
+                commands
+            }
+            Some(GamePacket::Connection(
+                ConnectionPacket::HandshakeRequested | ConnectionPacket::HandshakeEstablished,
+            )) => {
+                session.key_pending = true;
+                session.state = SessionState::HandshakeSeen;
+                Vec::new()
+            }
+            Some(GamePacket::Connection(ConnectionPacket::Disconnected)) => {
+                tracing::info!("Game session {client} disconnected");
+                session.state = SessionState::Ended(SessionEnd::Disconnected);
+                Vec::new()
+            }
+            _ => {
+                // Game traffic that can't be decoded before any handshake
+                // means the capture started after the session did, so
+                // nothing will ever decrypt.
+                if segment_key_id.is_some() && session.state == SessionState::WaitingForTraffic {
+                    session.undecodable_packets += 1;
+                    if session.undecodable_packets == MISSED_HANDSHAKE_PACKET_THRESHOLD {
+                        tracing::warn!(
+                            "Saw {} packets without a handshake for session {client}, \
+                             capture started mid session",
+                            session.undecodable_packets
+                        );
+                        session.state = SessionState::MissedHandshake;
+                    }
+                }
+                Vec::new()
+            }
         };
 
+        let state = session.state;
+        let ended = match state {
+            SessionState::Ended(end) => {
+                self.sessions.remove(&client);
+                Some(end)
+            }
+            _ => None,
+        };
+        if state != previous_state {
+            self.publish_session_state(ended);
+        }
+        if let Some(key_id) = session_key_id {
+            self.record_session_key(client, key_id);
+        }
+        if commands.is_empty() {
+            return;
+        }
+
         let log_packets = *self.log_packet_rx.borrow_and_update();
 
         let mut updated = self.app_state.app_state.updated.clone();
         let mut has_new_data = false;
+        let mut has_new_inventory = false;
 
         for command in commands {
             let _span = tracing::info_span!("packet id {}", command.command_id);
@@ -154,11 +445,13 @@ impl Monitor {
                 self.player_data.process_items(&items);
                 updated.items_updated = Some(Instant::now());
                 has_new_data = true;
+                has_new_inventory = true;
             } else if let Some(avatars) = matches_avatar_packet(&command) {
                 tracing::info!("Found avatar packet with {} avatars", avatars.len());
                 self.player_data.process_characters(&avatars);
                 updated.characters_updated = Some(Instant::now());
                 has_new_data = true;
+                has_new_inventory = true;
             } else if let Some(achievements) = matches_achievement_packet(&command) {
                 tracing::info!(
                     "Found achievement packet with {} achievements",
@@ -172,30 +465,289 @@ impl Monitor {
 
         if has_new_data {
             self.app_state.update_timestamps(updated);
+            if let Some(session) = self.sessions.get_mut(&client) {
+                session.state = SessionState::PlayerDataReceived;
+            }
+            self.publish_session_state(None);
+        }
+
+        if has_new_inventory {
+            self.check_unknown_ids();
+        }
+    }
+
+    /// Record which key a new session's first command was encrypted with.
+    fn record_session_key(&mut self, client: SocketAddr, key_id: u16) {
+        let Some(session) = self.sessions.get(&client) else {
+            return;
+        };
+
+        let session_key = SessionKey {
+            key_id,
+            source: self.key_sources.get(&key_id).copied(),
+            started: session.started,
+            client,
+            server: session.server,
+            interface: session.interface.clone(),
+        };
+        if session_key.source.is_some() {
+            tracing::info!("Session {client} using {session_key}");
+        } else {
+            tracing::warn!("Session {client} using {session_key}, its traffic can't be decrypted");
+        }
+        self.app_state.add_session_key(session_key);
+    }
+
+    /// End sessions once the game has gone quiet for long enough.
+    fn check_session_timeout(&mut self) {
+        let mut timed_out = false;
+        self.sessions.retain(|client, session| {
+            if session.last_packet.elapsed() < SESSION_TIMEOUT {
+                return true;
+            }
+            tracing::info!("No traffic for session {client} in {SESSION_TIMEOUT:?}, ending it");
+            timed_out |= session.state.is_active();
+            false
+        });
+        if timed_out {
+            self.publish_session_state(Some(SessionEnd::Timeout));
+        }
+    }
+
+    /// Show the state of the sessions being captured after one of them
+    /// changed, `ended` being set when the change ended it.
+    fn publish_session_state(&mut self, ended: Option<SessionEnd>) {
+        let states = self.sessions.values().map(|session| session.state);
+        if let Some(state) = shown_session_state(states, ended) {
+            self.app_state.update_session_state(state);
         }
     }
+
+    /// Publish how many packets this capture has dropped because the monitor
+    /// fell behind, logging whenever more are dropped.
+    fn check_dropped_packets(&mut self) {
+        let dropped_packets = self.dropped_packets.load(Ordering::Relaxed);
+        if dropped_packets > self.app_state.app_state.dropped_packets {
+            tracing::warn!("Dropped {dropped_packets} packets so far this capture");
+        }
+        self.app_state.update_dropped_packets(dropped_packets);
+    }
+
+    /// Report ids the game data doesn't know about, once per capture for each
+    /// new set of them.
+    fn check_unknown_ids(&mut self) {
+        let unknown_ids = self.player_data.unknown_ids();
+        if unknown_ids.is_empty() || unknown_ids == self.app_state.app_state.unknown_ids {
+            return;
+        }
+
+        tracing::warn!(
+            "Captured data has ids missing from the game data: {}",
+            unknown_ids.summary()
+        );
+        match save_unknown_id_report(&unknown_ids) {
+            Ok(path) => tracing::info!("Unknown id report saved to {}", path.display()),
+            Err(e) => tracing::error!("Unable to save unknown id report: {e}"),
+        }
+        self.app_state.update_unknown_ids(unknown_ids);
+    }
 }
 
+/// Load game data from `game_data_path` when one is given, reporting why to
+/// the UI when it can't be.  Otherwise check for newer game data than what
+/// was last downloaded and, once the user confirms, download it to the data
+/// dir.  A skipped download isn't offered again for a week.  Falls back to
+/// the previously downloaded data when it is newer than the data embedded at
+/// build time, then to the embedded data.
 async fn get_database(
     app_state: &mut AppStateManager,
-    _ui_message_rx: &mut mpsc::UnboundedReceiver<Message>,
+    ui_message_rx: &mut mpsc::UnboundedReceiver<Message>,
+    game_data_path: Option<PathBuf>,
 ) -> Result<AnimeGameData> {
     app_state.update_app_state(State::CheckingForData);
 
+    if let Some(path) = game_data_path {
+        match load_game_data_blocking(path.clone()).await {
+            Ok(db) => {
+                tracing::info!("Loaded game data from {}", path.display());
+                return Ok(db);
+            }
+            Err(e) => {
+                let error = format!(
+                    "Unable to load game data from {}, using the latest game data instead: {e}",
+                    path.display()
+                );
+                tracing::error!("{error}");
+                app_state.update_game_data_error(error);
+            }
+        }
+    }
+
+    let Some(dir) = crate::data_dir() else {
+        tracing::warn!("Data dir not found, using embedded game data");
+        return embedded_game_data();
+    };
+    let data_path = dir.join(GAME_DATA_FILE);
+    let skipped_path = dir.join(GAME_DATA_SKIPPED_FILE);
+
+    let mut db = AnimeGameData::new_with_cache(&dir.join(GAME_DATA_CACHE_FILE));
+    let needs_update = if download_recently_skipped(&skipped_path) {
+        tracing::info!("Game data download was skipped recently, not checking for updates");
+        Ok(Ok(false))
+    } else {
+        tokio::time::timeout(GAME_DATA_CHECK_TIMEOUT, db.needs_update()).await
+    };
+    match needs_update {
+        Ok(Ok(true)) => {
+            let confirmation_type = if data_path.exists() {
+                ConfirmationType::Update
+            } else {
+                ConfirmationType::Initial
+            };
+            app_state.update_app_state(State::WaitingForDownloadConfirmation(confirmation_type));
+
+            if wait_for_download_confirmation(ui_message_rx).await {
+                app_state.update_app_state(State::Downloading);
+                match download_game_data(&mut db, &data_path).await {
+                    Ok(()) => {
+                        let _ = fs::remove_file(&skipped_path);
+                        return Ok(db);
+                    }
+                    Err(e) => tracing::error!("Unable to download game data: {e}"),
+                }
+            } else if let Err(e) =
+                fs::create_dir_all(&dir).and_then(|()| File::create(&skipped_path))
+            {
+                tracing::warn!("Unable to remember the skipped game data download: {e}");
+            }
+        }
+        Ok(Ok(false)) => (),
+        Ok(Err(e)) => tracing::warn!("Unable to check for game data updates: {e}"),
+        Err(_) => tracing::warn!("Timed out checking for game data updates"),
+    }
+
+    if data_path.exists() {
+        if downloaded_after(&data_path, embedded_game_data_time()) {
+            match load_game_data_blocking(data_path).await {
+                Ok(db) => return Ok(db),
+                Err(e) => tracing::warn!("Unable to load downloaded game data: {e}"),
+            }
+        } else {
+            // Left over from before an app update with newer embedded data.
+            tracing::info!("Embedded game data is newer than the downloaded copy, removing it");
+            if let Err(e) = fs::remove_file(&data_path) {
+                tracing::warn!("Unable to remove outdated game data: {e}");
+            }
+        }
+    }
+    embedded_game_data()
+}
+
+/// Whether the game data at `data_path` was downloaded after `time`, going
+/// by when it was written.
+fn downloaded_after(data_path: &Path, time: SystemTime) -> bool {
+    fs::metadata(data_path)
+        .and_then(|metadata| metadata.modified())
+        .is_ok_and(|downloaded| downloaded > time)
+}
+
+/// When the game data embedded at build time was downloaded.
+fn embedded_game_data_time() -> SystemTime {
+    let secs = env!("EMBEDDED_GAME_DATA_TIME").parse().unwrap_or(0);
+    SystemTime::UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+/// Whether the user skipped a game data download within the last
+/// `GAME_DATA_SKIP_DURATION`, going by when `skipped_path` was written.
+fn download_recently_skipped(skipped_path: &Path) -> bool {
+    fs::metadata(skipped_path)
+        .and_then(|metadata| metadata.modified())
+        .is_ok_and(|skipped| {
+            skipped
+                .elapsed()
+                .is_ok_and(|elapsed| elapsed < GAME_DATA_SKIP_DURATION)
+        })
+}
+
+async fn wait_for_download_confirmation(
+    ui_message_rx: &mut mpsc::UnboundedReceiver<Message>,
+) -> bool {
+    while let Some(msg) = ui_message_rx.recv().await {
+        match msg {
+            Message::DownloadAcknowledged => return true,
+            Message::DownloadSkipped => return false,
+            msg => tracing::warn!("Ignoring {msg:?} while waiting for download confirmation"),
+        }
+    }
+    false
+}
+
+async fn download_game_data(db: &mut AnimeGameData, data_path: &Path) -> Result<()> {
+    db.update().await?;
+
+    // Write to a temporary file first so an interrupted save doesn't leave
+    // behind a truncated copy.
+    let tmp_path = data_path.with_extension("gz.tmp");
+    if let Some(dir) = data_path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let file = File::create(&tmp_path)
+        .with_context(|| format!("Unable to create {}", tmp_path.display()))?;
+    let mut writer = GzEncoder::new(BufWriter::new(file), Compression::default());
+    db.save_to_writer(&mut writer)?;
+    writer.finish()?.flush()?;
+    fs::rename(&tmp_path, data_path)?;
+
+    tracing::info!("Game data saved to {}", data_path.display());
+    Ok(())
+}
+
+/// Load game data from a JSON file, gzipped or not.
+pub fn load_game_data(path: &Path) -> Result<AnimeGameData> {
+    const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+    let file = File::open(path).with_context(|| format!("Unable to open {}", path.display()))?;
+    let mut reader = BufReader::new(file);
+    if reader.fill_buf()?.starts_with(&GZIP_MAGIC) {
+        anime_game_data::AnimeGameData::new_from_reader(GzDecoder::new(reader))
+    } else {
+        anime_game_data::AnimeGameData::new_from_reader(reader)
+    }
+}
+
+/// Load game data with `load_game_data` on a blocking thread, as parsing it
+/// takes a while.
+async fn load_game_data_blocking(path: PathBuf) -> Result<AnimeGameData> {
+    tokio::task::spawn_blocking(move || load_game_data(&path)).await?
+}
+
+/// Load the game data embedded in the binary at build time.
+pub fn embedded_game_data() -> Result<AnimeGameData> {
     static DATABASE: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/game_data.gz"));
     let reader = GzDecoder::new(DATABASE);
-    let db = anime_game_data::AnimeGameData::new_from_reader(reader)?;
-
-    Ok(db)
+    anime_game_data::AnimeGameData::new_from_reader(reader)
 }
 
+/// Pass captured packets on to the monitor, dropping them when the monitor
+/// has fallen a full queue behind rather than letting the backlog grow.
+/// `dropped_packets` is kept up to date with the packets dropped here and by
+/// the backend.
+///
+/// When the capture stops, for instance because its device went away, it is
+/// recreated from `settings` with an exponential backoff.
 async fn capture_task(
     cancel_token: CancellationToken,
-    packet_tx: mpsc::UnboundedSender<Vec<u8>>,
+    packet_tx: mpsc::Sender<Packet>,
+    event_tx: mpsc::UnboundedSender<CaptureEvent>,
     backend: BackendType,
+    settings: CaptureSettings,
+    mut capture: Box<dyn CaptureBackend>,
+    dropped_packets: Arc<AtomicU64>,
 ) -> Result<()> {
-    let mut capture = create_capture(backend)
-        .map_err(|e| anyhow!("Error creating packet capture using {:?}: {e}", backend))?;
+    let mut queue_full_drops = 0;
+    // Packets dropped by backends that have since been restarted.
+    let mut earlier_backend_drops = 0;
+    let mut restart_delay = RESTART_DELAY_MIN;
     tracing::info!("starting capture");
     loop {
         let packet = tokio::select!(
@@ -205,21 +757,108 @@ async fn capture_task(
         let packet = match packet {
             Ok(packet) => packet,
             Err(e) => {
-                tracing::error!("Error receiving packet: {e}");
-                continue;
+                tracing::error!("Capture stopped: {e}");
+                earlier_backend_drops += capture.dropped_packets();
+                let restarted = restart_capture(
+                    &cancel_token,
+                    &event_tx,
+                    backend,
+                    &settings,
+                    e,
+                    &mut restart_delay,
+                )
+                .await;
+                match restarted {
+                    Some(restarted) => {
+                        capture = restarted;
+                        continue;
+                    }
+                    None => break,
+                }
             }
         };
+        // Only back off further while restarted captures keep failing
+        // before anything is captured.
+        restart_delay = RESTART_DELAY_MIN;
 
-        if let Err(e) = packet_tx.send(packet) {
+        let sent = if packet.is_handshake() {
+            packet_tx.send(packet).await.map_err(|e| e.to_string())
+        } else {
+            match packet_tx.try_send(packet) {
+                Err(TrySendError::Full(_)) => {
+                    queue_full_drops += 1;
+                    Ok(())
+                }
+                result => result.map_err(|e| e.to_string()),
+            }
+        };
+        if let Err(e) = sent {
             tracing::error!("Error sending captured packet to monitor: {e}");
         }
+        dropped_packets.store(
+            queue_full_drops + earlier_backend_drops + capture.dropped_packets(),
+            Ordering::Relaxed,
+        );
     }
     tracing::info!("ending capture");
     Ok(())
 }
 
+/// Recreate a capture that stopped with `error`, waiting `restart_delay`
+/// before each attempt and doubling it afterwards.  Devices are enumerated
+/// again on each attempt, so a capture whose device went away picks up its
+/// replacement.
+///
+/// Returns `None` if the capture is cancelled first.
+async fn restart_capture(
+    cancel_token: &CancellationToken,
+    event_tx: &mpsc::UnboundedSender<CaptureEvent>,
+    backend: BackendType,
+    settings: &CaptureSettings,
+    error: CaptureError,
+    restart_delay: &mut Duration,
+) -> Option<Box<dyn CaptureBackend>> {
+    let mut error = error.to_string();
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        let _ = event_tx.send(CaptureEvent::Interrupted { error, attempt });
+        tracing::info!("Restarting capture in {restart_delay:?} (attempt {attempt})");
+        tokio::select! {
+            _ = tokio::time::sleep(*restart_delay) => (),
+            _ = cancel_token.cancelled() => return None,
+        }
+        *restart_delay = (*restart_delay * 2).min(RESTART_DELAY_MAX);
+
+        match create_capture_with_fallback(backend, settings) {
+            Ok((backend, capture)) => {
+                tracing::info!("Capture restarted with {backend:?}");
+                let _ = event_tx.send(CaptureEvent::Restarted(backend));
+                return Some(capture);
+            }
+            Err(e) => {
+                tracing::warn!("Unable to restart capture: {e}");
+                error = e.to_string();
+            }
+        }
+    }
+}
+
+fn save_unknown_id_report(report: &UnknownIdReport) -> Result<PathBuf> {
+    let mut path = crate::log_dir()?;
+    fs::create_dir_all(&path)?;
+    path.push(format!(
+        "unknown_ids_{}.json",
+        Local::now().format("%Y-%m-%d_%H-%M-%S")
+    ));
+
+    let file = File::create(&path).with_context(|| format!("can't create file {path:?}"))?;
+    serde_json::to_writer_pretty(BufWriter::new(file), report)?;
+    Ok(path)
+}
+
 fn log_command(command: &GameCommand) -> Result<()> {
-    let mut packet_log_path = eframe::storage_dir(APP_ID).context("Storage dir not found")?;
+    let mut packet_log_path = crate::data_dir().context("Data dir not found")?;
     packet_log_path.push("packet_log");
     fs::create_dir_all(&packet_log_path)?;
 
@@ -238,10 +877,106 @@ fn log_command(command: &GameCommand) -> Result<()> {
     Ok(())
 }
 
-fn load_keys() -> Result<HashMap<u16, Vec<u8>>> {
-    let keys: HashMap<u16, String> = serde_json::from_slice(include_bytes!("../keys/gi.json"))?;
+/// The state to show for sessions in `states`: the furthest along active one,
+/// so a relog's old session ending doesn't hide the new one.  With none
+/// active, how the last session `ended`, if it just did.
+fn shown_session_state(
+    states: impl IntoIterator<Item = SessionState>,
+    ended: Option<SessionEnd>,
+) -> Option<SessionState> {
+    let progress = |state: SessionState| match state {
+        SessionState::MissedHandshake => Some(0),
+        SessionState::HandshakeSeen => Some(1),
+        SessionState::KeysEstablished => Some(2),
+        SessionState::PlayerDataReceived => Some(3),
+        SessionState::WaitingForTraffic | SessionState::Ended(_) => None,
+    };
+    states
+        .into_iter()
+        .filter_map(|state| Some((progress(state)?, state)))
+        .max_by_key(|(progress, _)| *progress)
+        .map(|(_, state)| state)
+        .or(ended.map(SessionState::Ended))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skipped_downloads_are_remembered() {
+        let dir = tempfile::tempdir().unwrap();
+        let skipped_path = dir.path().join(GAME_DATA_SKIPPED_FILE);
+        assert!(!download_recently_skipped(&skipped_path));
+
+        File::create(&skipped_path).unwrap();
+        assert!(download_recently_skipped(&skipped_path));
+    }
+
+    #[test]
+    fn downloaded_game_data_older_than_the_embedded_data_is_not_used() {
+        let dir = tempfile::tempdir().unwrap();
+        let data_path = dir.path().join(GAME_DATA_FILE);
+        assert!(!downloaded_after(&data_path, SystemTime::UNIX_EPOCH));
+
+        File::create(&data_path).unwrap();
+        assert!(downloaded_after(&data_path, embedded_game_data_time()));
+        assert!(!downloaded_after(
+            &data_path,
+            SystemTime::now() + Duration::from_secs(60)
+        ));
+    }
 
-    keys.iter()
-        .map(|(key, value)| -> Result<_, _> { Ok((*key, BASE64_STANDARD.decode(value)?)) })
-        .collect::<Result<HashMap<_, _>>>()
+    #[test]
+    fn an_old_session_ending_does_not_hide_a_live_one() {
+        // The relogged session has its player data while the old one times
+        // out.
+        assert_eq!(
+            shown_session_state(
+                [SessionState::PlayerDataReceived],
+                Some(SessionEnd::Timeout)
+            ),
+            Some(SessionState::PlayerDataReceived)
+        );
+        assert_eq!(
+            shown_session_state(
+                [
+                    SessionState::KeysEstablished,
+                    SessionState::WaitingForTraffic
+                ],
+                Some(SessionEnd::Disconnected)
+            ),
+            Some(SessionState::KeysEstablished)
+        );
+    }
+
+    #[test]
+    fn the_furthest_along_session_is_shown() {
+        assert_eq!(
+            shown_session_state(
+                [
+                    SessionState::PlayerDataReceived,
+                    SessionState::HandshakeSeen
+                ],
+                None
+            ),
+            Some(SessionState::PlayerDataReceived)
+        );
+        assert_eq!(
+            shown_session_state(
+                [SessionState::MissedHandshake, SessionState::KeysEstablished],
+                None
+            ),
+            Some(SessionState::KeysEstablished)
+        );
+    }
+
+    #[test]
+    fn the_end_is_shown_once_no_session_is_active() {
+        assert_eq!(
+            shown_session_state([SessionState::WaitingForTraffic], Some(SessionEnd::Timeout)),
+            Some(SessionState::Ended(SessionEnd::Timeout))
+        );
+        assert_eq!(shown_session_state([], None), None);
+    }
 }
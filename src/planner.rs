@@ -0,0 +1,412 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::good;
+
+/// EXP needed to go from level `n + 1` to level `n + 2`.
+const LEVEL_EXP: [u64; 89] = [
+    1000, 1325, 1700, 2150, 2625, 3150, 3725, 4350, 5000, 5700, 6450, 7225, 8050, 8925, 9825,
+    10750, 11725, 12725, 13775, 14875, 16800, 18000, 19250, 20550, 21875, 23250, 24650, 26100,
+    27575, 29100, 30650, 32250, 33875, 35550, 37250, 38975, 40750, 42575, 44425, 46300, 50625,
+    52700, 54775, 56900, 59075, 61275, 63525, 65800, 68125, 70475, 76500, 79050, 81650, 84275,
+    86950, 89650, 92400, 95175, 98000, 100875, 108950, 112050, 115175, 118325, 121525, 124775,
+    128075, 131400, 134775, 138175, 148700, 152375, 156075, 159825, 163600, 167425, 171300, 175225,
+    179175, 183125, 216225, 243025, 273100, 306800, 344600, 386950, 434425, 487475, 547400,
+];
+
+/// Leveling costs one mora for every 5 EXP fed to a character.
+const EXP_PER_MORA: u64 = 5;
+
+/// Max character level at each ascension phase.
+const ASCENSION_LEVEL_CAP: [u32; 7] = [20, 40, 50, 60, 70, 80, 90];
+
+/// Costs of ascending from phase `n` to phase `n + 1`.
+const ASCENSION_MORA: [u64; 6] = [20000, 40000, 60000, 80000, 100000, 120000];
+const ASCENSION_BOSS_DROPS: [u32; 6] = [0, 2, 4, 8, 12, 20];
+
+/// Costs of raising a talent from level `n + 1` to level `n + 2`.
+const TALENT_MORA: [u64; 9] = [
+    12500, 17500, 25000, 30000, 37500, 120000, 260000, 450000, 700000,
+];
+/// Talent books as (tier, count), where tier 0 is "Teachings", 1 is "Guide"
+/// and 2 is "Philosophies".
+const TALENT_BOOKS: [(usize, u32); 9] = [
+    (0, 3),
+    (1, 2),
+    (1, 4),
+    (1, 6),
+    (1, 9),
+    (2, 4),
+    (2, 6),
+    (2, 12),
+    (2, 16),
+];
+const TALENT_WEEKLY_BOSS_DROPS: [u32; 9] = [0, 0, 0, 0, 0, 1, 1, 1, 1];
+const TALENT_CROWNS: [u32; 9] = [0, 0, 0, 0, 0, 0, 0, 0, 1];
+
+/// EXP books by GOOD key and the EXP each one is worth.
+const EXP_BOOKS: [(&str, u64); 3] = [
+    ("HerosWit", 20000),
+    ("AdventurersExperience", 5000),
+    ("WanderersAdvice", 1000),
+];
+const HEROS_WIT_EXP: u64 = 20000;
+const CROWN_OF_INSIGHT_KEY: &str = "CrownOfInsight";
+/// What a character should be raised to.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Goal {
+    pub level: u32,
+    pub ascension: u32,
+    pub auto: u32,
+    pub skill: u32,
+    pub burst: u32,
+}
+
+impl From<&good::Character> for Goal {
+    fn from(character: &good::Character) -> Self {
+        Self {
+            level: character.level,
+            ascension: character.ascension,
+            auto: character.talent.auto,
+            skill: character.talent.skill,
+            burst: character.talent.burst,
+        }
+    }
+}
+
+/// Materials needed to reach one or more goals.
+///
+/// Boss drops, talent books and weekly boss drops differ from character to
+/// character and the game data doesn't record which, so they are counted
+/// across all kinds rather than per item.  Owning one kind doesn't cover the
+/// need for another, so they are never taken as owned.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct Cost {
+    pub mora: u64,
+    pub exp: u64,
+    pub boss_drops: u32,
+    /// Talent books by tier: Teachings, Guide, Philosophies.
+    pub talent_books: [u32; 3],
+    pub weekly_boss_drops: u32,
+    pub crowns: u32,
+}
+
+impl Cost {
+    fn add(&mut self, other: &Cost) {
+        self.mora += other.mora;
+        self.exp += other.exp;
+        self.boss_drops += other.boss_drops;
+        for (total, books) in self.talent_books.iter_mut().zip(other.talent_books) {
+            *total += books;
+        }
+        self.weekly_boss_drops += other.weekly_boss_drops;
+        self.crowns += other.crowns;
+    }
+
+    /// What's left of this cost after spending `owned`.
+    fn saturating_sub(&self, owned: &Cost) -> Cost {
+        let mut talent_books = self.talent_books;
+        for (books, owned) in talent_books.iter_mut().zip(owned.talent_books) {
+            *books = books.saturating_sub(owned);
+        }
+        Cost {
+            mora: self.mora.saturating_sub(owned.mora),
+            exp: self.exp.saturating_sub(owned.exp),
+            boss_drops: self.boss_drops.saturating_sub(owned.boss_drops),
+            talent_books,
+            weekly_boss_drops: self
+                .weekly_boss_drops
+                .saturating_sub(owned.weekly_boss_drops),
+            crowns: self.crowns.saturating_sub(owned.crowns),
+        }
+    }
+
+    /// The materials in `materials`, an inventory by GOOD key, that count
+    /// towards a cost.  Mora isn't in the inventory, so is never owned, and
+    /// materials counted across all kinds aren't either.
+    fn owned(materials: &HashMap<String, u32>) -> Cost {
+        let mut owned = Cost::default();
+        for (key, &count) in materials {
+            if let Some((_, exp)) = EXP_BOOKS.iter().find(|(book, _)| book == key) {
+                owned.exp += u64::from(count) * exp;
+            } else if key == CROWN_OF_INSIGHT_KEY {
+                owned.crowns += count;
+            }
+        }
+        owned
+    }
+
+    /// The number of Hero's Wits needed to cover `exp`.
+    pub fn heros_wits(&self) -> u64 {
+        self.exp.div_ceil(HEROS_WIT_EXP)
+    }
+
+    fn add_levels(&mut self, from: u32, to: u32) {
+        for level in from.max(1)..to.min(90) {
+            let exp = LEVEL_EXP[level as usize - 1];
+            self.exp += exp;
+            self.mora += exp / EXP_PER_MORA;
+        }
+    }
+
+    fn add_ascensions(&mut self, from: u32, to: u32) {
+        for phase in from..to.min(6) {
+            self.mora += ASCENSION_MORA[phase as usize];
+            self.boss_drops += ASCENSION_BOSS_DROPS[phase as usize];
+        }
+    }
+
+    fn add_talent_levels(&mut self, from: u32, to: u32) {
+        for level in from.max(1)..to.min(10) {
+            let index = level as usize - 1;
+            let (tier, books) = TALENT_BOOKS[index];
+            self.mora += TALENT_MORA[index];
+            self.talent_books[tier] += books;
+            self.weekly_boss_drops += TALENT_WEEKLY_BOSS_DROPS[index];
+            self.crowns += TALENT_CROWNS[index];
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct CharacterPlan {
+    pub current: good::Character,
+    pub goal: Goal,
+    pub cost: Cost,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Plan {
+    pub characters: Vec<CharacterPlan>,
+    /// Everything needed to reach every goal.
+    pub total: Cost,
+    /// What's left of `total` after spending the materials in the inventory.
+    pub missing: Cost,
+}
+
+/// The ascension phase needed to reach `level`.
+fn min_ascension(level: u32) -> u32 {
+    ASCENSION_LEVEL_CAP
+        .iter()
+        .position(|cap| level <= *cap)
+        .unwrap_or(ASCENSION_LEVEL_CAP.len() - 1) as u32
+}
+
+fn character_cost(current: &good::Character, goal: &Goal) -> Cost {
+    let mut cost = Cost::default();
+    cost.add_levels(current.level, goal.level);
+    cost.add_ascensions(
+        current.ascension,
+        goal.ascension.max(min_ascension(goal.level)),
+    );
+    cost.add_talent_levels(current.talent.auto, goal.auto);
+    cost.add_talent_levels(current.talent.skill, goal.skill);
+    cost.add_talent_levels(current.talent.burst, goal.burst);
+    cost
+}
+
+/// Work out the materials needed to raise `characters` to their `goals`.
+///
+/// Characters without a goal are kept at their current levels.
+pub fn plan(
+    characters: &[good::Character],
+    materials: &HashMap<String, u32>,
+    goals: &HashMap<String, Goal>,
+) -> Plan {
+    let mut plan = Plan::default();
+
+    for character in characters {
+        let goal = goals
+            .get(&character.key)
+            .copied()
+            .unwrap_or_else(|| character.into());
+        let cost = character_cost(character, &goal);
+        plan.total.add(&cost);
+        plan.characters.push(CharacterPlan {
+            current: character.clone(),
+            goal,
+            cost,
+        });
+    }
+
+    plan.missing = plan.total.saturating_sub(&Cost::owned(materials));
+
+    plan
+}
+
+impl Plan {
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn to_csv(&self) -> Result<String> {
+        fn cost_fields(cost: &Cost) -> Vec<String> {
+            vec![
+                cost.mora.to_string(),
+                cost.exp.to_string(),
+                cost.heros_wits().to_string(),
+                cost.boss_drops.to_string(),
+                cost.talent_books[0].to_string(),
+                cost.talent_books[1].to_string(),
+                cost.talent_books[2].to_string(),
+                cost.weekly_boss_drops.to_string(),
+                cost.crowns.to_string(),
+            ]
+        }
+
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        writer.write_record([
+            "character",
+            "level",
+            "ascension",
+            "auto",
+            "skill",
+            "burst",
+            "goal_level",
+            "goal_ascension",
+            "goal_auto",
+            "goal_skill",
+            "goal_burst",
+            "mora",
+            "exp",
+            "heros_wits",
+            "boss_drops",
+            "teachings",
+            "guides",
+            "philosophies",
+            "weekly_boss_drops",
+            "crowns",
+        ])?;
+
+        for character in &self.characters {
+            let current = &character.current;
+            let goal = &character.goal;
+            let mut record = vec![
+                current.key.clone(),
+                current.level.to_string(),
+                current.ascension.to_string(),
+                current.talent.auto.to_string(),
+                current.talent.skill.to_string(),
+                current.talent.burst.to_string(),
+                goal.level.to_string(),
+                goal.ascension.to_string(),
+                goal.auto.to_string(),
+                goal.skill.to_string(),
+                goal.burst.to_string(),
+            ];
+            record.extend(cost_fields(&character.cost));
+            writer.write_record(record)?;
+        }
+
+        for (name, cost) in [("Total", &self.total), ("Missing", &self.missing)] {
+            let mut record = vec![name.to_string()];
+            record.extend(std::iter::repeat_n(String::new(), 10));
+            record.extend(cost_fields(cost));
+            writer.write_record(record)?;
+        }
+
+        Ok(String::from_utf8(writer.into_inner()?)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn character(level: u32, ascension: u32, talent: u32) -> good::Character {
+        good::Character {
+            key: "Furina".to_owned(),
+            level,
+            constellation: 0,
+            ascension,
+            talent: good::TalentLevel {
+                auto: talent,
+                skill: talent,
+                burst: talent,
+            },
+        }
+    }
+
+    fn goals(goal: Goal) -> HashMap<String, Goal> {
+        HashMap::from([("Furina".to_owned(), goal)])
+    }
+
+    const MAXED: Goal = Goal {
+        level: 90,
+        ascension: 6,
+        auto: 10,
+        skill: 10,
+        burst: 10,
+    };
+
+    #[test]
+    fn maxing_a_new_character_costs_the_full_tables() {
+        let plan = plan(&[character(1, 0, 1)], &HashMap::new(), &goals(MAXED));
+
+        assert_eq!(
+            plan.total,
+            Cost {
+                mora: 8_362_650 / 5 + 420_000 + 3 * 1_652_500,
+                exp: 8_362_650,
+                boss_drops: 46,
+                talent_books: [9, 63, 114],
+                weekly_boss_drops: 12,
+                crowns: 3,
+            }
+        );
+        assert_eq!(plan.total.heros_wits(), 419);
+    }
+
+    #[test]
+    fn a_level_goal_includes_the_ascensions_it_needs() {
+        let goal = Goal {
+            level: 80,
+            ascension: 0,
+            auto: 1,
+            skill: 1,
+            burst: 1,
+        };
+        let plan = plan(&[character(20, 0, 1)], &HashMap::new(), &goals(goal));
+
+        assert_eq!(plan.total.boss_drops, 26);
+    }
+
+    #[test]
+    fn characters_without_a_goal_cost_nothing() {
+        let plan = plan(&[character(1, 0, 1)], &HashMap::new(), &HashMap::new());
+
+        assert_eq!(plan.total, Cost::default());
+    }
+
+    #[test]
+    fn exp_books_and_crowns_in_the_inventory_are_spent() {
+        let materials = HashMap::from([
+            ("HerosWit".to_owned(), 400),
+            ("WanderersAdvice".to_owned(), 2),
+            ("CrownOfInsight".to_owned(), 5),
+        ]);
+        let plan = plan(&[character(1, 0, 1)], &materials, &goals(MAXED));
+
+        assert_eq!(plan.missing.exp, 8_362_650 - 400 * 20_000 - 2 * 1_000);
+        assert_eq!(plan.missing.crowns, 0);
+        assert_eq!(plan.missing.boss_drops, plan.total.boss_drops);
+    }
+
+    #[test]
+    fn materials_counted_across_kinds_are_not_spent() {
+        // Hurricane Seeds don't ascend a character that needs Lightning
+        // Prisms, so owning them can't make the boss drops less missing.
+        let materials = HashMap::from([
+            ("HurricaneSeed".to_owned(), 46),
+            ("TeachingsOfFreedom".to_owned(), 20),
+            ("GuideToJustice".to_owned(), 63),
+            ("PhilosophiesOfEquity".to_owned(), 114),
+            ("DvalinsPlume".to_owned(), 12),
+        ]);
+        let plan = plan(&[character(1, 0, 1)], &materials, &goals(MAXED));
+
+        assert_eq!(plan.missing, plan.total);
+    }
+}
@@ -1,4 +1,5 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Display;
 
 use anime_game_data::{AnimeGameData, Property, SkillType};
 use anyhow::Result;
@@ -7,7 +8,9 @@ pub use auto_artifactarium::r#gen::protos::{AvatarInfo, Item};
 use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
 
+use crate::artifacts::{self, ArtifactAnalysis, CleanupRecommendation, ScoreSettings};
 use crate::good::{self, fake_uninitialized_4th_line};
+use crate::{planner, spreadsheet};
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct ExportSettings {
@@ -30,6 +33,79 @@ pub struct ExportSettings {
     pub min_weapon_rarity: u32,
 }
 
+/// An artifact along with the rolls that make up each of its substats.
+#[derive(Clone, Debug)]
+pub struct RolledArtifact {
+    pub guid: u64,
+    pub artifact: good::Artifact,
+    /// The unrounded value of every roll of each substat, in the same order as
+    /// `artifact.substats`.  The first roll is the substat's initial value.
+    pub rolls: Vec<Vec<f32>>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub enum IdKind {
+    Character,
+    Weapon,
+    Artifact,
+    Property,
+    Affix,
+    Material,
+}
+
+impl Display for IdKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IdKind::Character => write!(f, "character"),
+            IdKind::Weapon => write!(f, "weapon"),
+            IdKind::Artifact => write!(f, "artifact"),
+            IdKind::Property => write!(f, "main stat"),
+            IdKind::Affix => write!(f, "substat"),
+            IdKind::Material => write!(f, "material"),
+        }
+    }
+}
+
+/// An id in the captured data that the game data has no entry for.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct UnknownId {
+    pub id: u32,
+    pub kind: IdKind,
+    /// The field of the captured data the id was found in.
+    pub field: &'static str,
+    /// How many times the id was seen in `field`.
+    pub count: u32,
+}
+
+/// Ids that were skipped when exporting because the game data doesn't know
+/// about them, which usually means it is out of date.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct UnknownIdReport {
+    pub ids: Vec<UnknownId>,
+}
+
+impl UnknownIdReport {
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    /// Number of distinct unknown ids of each kind, e.g. "2 weapons, 1 material".
+    pub fn summary(&self) -> String {
+        let mut kinds: BTreeMap<IdKind, usize> = BTreeMap::new();
+        for id in &self.ids {
+            *kinds.entry(id.kind).or_default() += 1;
+        }
+        kinds
+            .iter()
+            .map(|(kind, count)| match count {
+                1 => format!("1 {kind}"),
+                count => format!("{count} {kind}s"),
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
 pub struct PlayerData {
     game_data: AnimeGameData,
     achievements: Vec<Achievement>,
@@ -69,7 +145,97 @@ impl PlayerData {
         self.items = items.into();
     }
 
+    pub fn game_data(&self) -> &AnimeGameData {
+        &self.game_data
+    }
+
+    pub fn characters(&self) -> &[AvatarInfo] {
+        &self.characters
+    }
+
+    pub fn items(&self) -> &[Item] {
+        &self.items
+    }
+
+    /// Find the ids in the captured characters and items that are missing
+    /// from the game data.
+    pub fn unknown_ids(&self) -> UnknownIdReport {
+        let mut unknown: BTreeMap<(IdKind, &'static str, u32), u32> = BTreeMap::new();
+        let mut check = |known: bool, kind, field, id| {
+            if !known {
+                *unknown.entry((kind, field, id)).or_default() += 1;
+            }
+        };
+
+        for character in &self.characters {
+            if character.avatar_type == 1 {
+                let known = self.game_data.get_character(character.avatar_id).is_ok();
+                check(known, IdKind::Character, "avatar_id", character.avatar_id);
+            }
+        }
+
+        for item in &self.items {
+            if item.has_material() {
+                let known = self.game_data.get_material(item.item_id).is_ok();
+                check(known, IdKind::Material, "item_id", item.item_id);
+                continue;
+            }
+            if !item.has_equip() {
+                continue;
+            }
+            let equip = item.equip();
+            if equip.has_weapon() {
+                let known = self.game_data.get_weapon(item.item_id).is_ok();
+                check(known, IdKind::Weapon, "item_id", item.item_id);
+            }
+            if equip.has_reliquary() {
+                let artifact = equip.reliquary();
+                let known = self.game_data.get_artifact(item.item_id).is_ok();
+                check(known, IdKind::Artifact, "item_id", item.item_id);
+                let known = self.game_data.get_property(artifact.main_prop_id).is_ok();
+                check(
+                    known,
+                    IdKind::Property,
+                    "main_prop_id",
+                    artifact.main_prop_id,
+                );
+                for id in &artifact.append_prop_id_list {
+                    let known = self.game_data.get_affix(*id).is_ok();
+                    check(known, IdKind::Affix, "append_prop_id_list", *id);
+                }
+                for id in &artifact.unactivated_prop_id_list {
+                    let known = self.game_data.get_affix(*id).is_ok();
+                    check(known, IdKind::Affix, "unactivated_prop_id_list", *id);
+                }
+            }
+        }
+
+        UnknownIdReport {
+            ids: unknown
+                .into_iter()
+                .map(|((kind, field, id), count)| UnknownId {
+                    id,
+                    kind,
+                    field,
+                    count,
+                })
+                .collect(),
+        }
+    }
+
     pub fn export_genshin_optimizer(&self, settings: &ExportSettings) -> Result<String> {
+        let json = serde_json::to_string(&self.good(settings))?;
+        tracing::trace!("{json}");
+        Ok(json)
+    }
+
+    /// Export the same data as [`Self::export_genshin_optimizer`] as
+    /// spreadsheet tables using the names from the game instead of GOOD keys.
+    pub fn export_spreadsheet(&self, settings: &ExportSettings) -> Vec<spreadsheet::Sheet> {
+        spreadsheet::sheets(&self.good(settings), &self.display_names())
+    }
+
+    fn good(&self, settings: &ExportSettings) -> good::Good {
         let mut good = good::Good {
             format: "GOOD".to_string(),
             version: 3,
@@ -101,15 +267,64 @@ impl PlayerData {
             good.materials = self.export_genshin_optimizer_materials();
         }
 
-        let json = serde_json::to_string(&good)?;
-        tracing::trace!("{json}");
-        Ok(json)
+        good
+    }
+
+    /// Map the GOOD keys of the player's characters, weapons, artifact sets
+    /// and materials back to their names in the game.
+    fn display_names(&self) -> HashMap<String, String> {
+        let characters = self
+            .characters
+            .iter()
+            .filter_map(|character| self.game_data.get_character(character.avatar_id).ok());
+        let items = self.items.iter().filter_map(|item| {
+            if item.has_material() {
+                self.game_data.get_material(item.item_id).ok()
+            } else if item.has_equip() && item.equip().has_weapon() {
+                self.game_data
+                    .get_weapon(item.item_id)
+                    .ok()
+                    .map(|weapon| &weapon.name)
+            } else if item.has_equip() && item.equip().has_reliquary() {
+                self.game_data
+                    .get_artifact(item.item_id)
+                    .ok()
+                    .map(|artifact| &artifact.set)
+            } else {
+                None
+            }
+        });
+
+        characters
+            .chain(items)
+            .map(|name| (good::to_good_key(name), name.clone()))
+            .collect()
     }
 
     pub fn export_genshin_optimizer_characters(
         &self,
         settings: &ExportSettings,
     ) -> Vec<good::Character> {
+        self.good_characters()
+            .into_iter()
+            .filter(|character| {
+                character.level >= settings.min_character_level
+                    && character.ascension >= settings.min_character_ascension
+                    && character.constellation >= settings.min_character_constellation
+            })
+            .collect()
+    }
+
+    /// Work out the materials needed to raise characters to `goals`.
+    pub fn plan(&self, goals: &HashMap<String, planner::Goal>) -> planner::Plan {
+        planner::plan(
+            &self.good_characters(),
+            &self.export_genshin_optimizer_materials(),
+            goals,
+        )
+    }
+
+    fn good_characters(&self) -> Vec<good::Character> {
         // TPS avatars are not normal characters and are excluded from export.
         let tps_avatar_ids: Vec<u32> = [
             self.game_data.get_tps_avatar_id_female(),
@@ -150,13 +365,6 @@ impl PlayerData {
                     }
                 }
 
-                if level < settings.min_character_level
-                    || ascension < settings.min_character_ascension
-                    || constellation < settings.min_character_constellation
-                {
-                    return None;
-                }
-
                 // The Traveler is the only character that can change elements.
                 // The GOOD format lets you optionally suffix the Traveler's
                 // name with their element (e.g. `TravelerCryo`).
@@ -191,6 +399,32 @@ impl PlayerData {
         &self,
         settings: &ExportSettings,
     ) -> Vec<good::Artifact> {
+        self.rolled_artifacts()
+            .into_iter()
+            .filter(|rolled| {
+                rolled.artifact.level >= settings.min_artifact_level
+                    && rolled.artifact.rarity >= settings.min_artifact_rarity
+            })
+            .map(|rolled| rolled.artifact)
+            .collect()
+    }
+
+    /// Work out the roll tiers and score of every artifact.
+    pub fn analyze_artifacts(&self, settings: &ScoreSettings) -> Vec<ArtifactAnalysis> {
+        artifacts::analyze(&self.rolled_artifacts(), settings)
+    }
+
+    /// Work out which artifacts are worth locking and which can be unlocked.
+    pub fn recommend_artifact_cleanup(
+        &self,
+        settings: &ScoreSettings,
+    ) -> Vec<CleanupRecommendation> {
+        artifacts::recommend_cleanup(&self.rolled_artifacts(), settings)
+    }
+
+    /// Decode every artifact along with the individual rolls behind each of
+    /// its substats.
+    pub fn rolled_artifacts(&self) -> Vec<RolledArtifact> {
         self.items
             .iter()
             .filter_map(|item| {
@@ -214,24 +448,25 @@ impl PlayerData {
                 }
                 let artifact_data = self.game_data.get_artifact(item.item_id).ok()?;
                 let artifact = equip.reliquary();
-                let mut substats: IndexMap<Property, (f32, f32)> = IndexMap::new();
+                let mut substat_rolls: IndexMap<Property, Vec<f32>> = IndexMap::new();
                 for substat_id in artifact.append_prop_id_list.iter() {
                     let Some(substat) = self.game_data.get_affix(*substat_id).ok() else {
                         continue;
                     };
-                    let entry = substats
+                    substat_rolls
                         .entry(substat.property)
-                        .or_insert((0., substat.value as f32));
-                    entry.0 += substat.value as f32;
+                        .or_default()
+                        .push(substat.value as f32);
                 }
-                let substats = substats
-                    .into_iter()
-                    .map(|(property, (value, initial_value))| good::Substat {
+                let substats = substat_rolls
+                    .iter()
+                    .map(|(property, rolls)| good::Substat {
                         key: property.good_name().to_string(),
-                        value: Self::round(property, value),
-                        initial_value: Self::round(property, initial_value),
+                        value: Self::round(*property, rolls.iter().sum()),
+                        initial_value: Self::round(*property, rolls[0]),
                     })
                     .collect();
+                let rolls = substat_rolls.into_values().collect();
                 let unactivated_substats = artifact
                     .unactivated_prop_id_list
                     .iter()
@@ -257,11 +492,7 @@ impl PlayerData {
                     .good_name()
                     .to_string();
 
-                if level < settings.min_artifact_level || rarity < settings.min_artifact_rarity {
-                    return None;
-                }
-
-                Some(good::Artifact {
+                let artifact = good::Artifact {
                     set_key: good::to_good_key(&artifact_data.set),
                     slot_key: artifact_data.slot.good_name().to_string(),
                     level,
@@ -274,6 +505,12 @@ impl PlayerData {
                     astral_mark,
                     elixer_crafted,
                     unactivated_substats,
+                };
+
+                Some(RolledArtifact {
+                    guid: item.guid,
+                    artifact,
+                    rolls,
                 })
             })
             .collect()
@@ -348,3 +585,43 @@ impl PlayerData {
             .collect()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use auto_artifactarium::r#gen::protos::Material;
+
+    use super::*;
+
+    #[test]
+    fn unknown_ids_are_counted_per_field() {
+        let mut player_data = PlayerData::new(crate::monitor::embedded_game_data().unwrap());
+        let items: Vec<Item> = (0..3)
+            .map(|guid| {
+                let mut item = Item {
+                    item_id: 1,
+                    guid,
+                    ..Default::default()
+                };
+                item.set_material(Material {
+                    count: 1,
+                    ..Default::default()
+                });
+                item
+            })
+            .collect();
+        player_data.process_items(&items);
+
+        let report = player_data.unknown_ids();
+
+        assert_eq!(
+            report.ids,
+            [UnknownId {
+                id: 1,
+                kind: IdKind::Material,
+                field: "item_id",
+                count: 3,
+            }]
+        );
+        assert_eq!(report.summary(), "1 material");
+    }
+}
@@ -0,0 +1,369 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use rust_xlsxwriter::{Format, Workbook};
+
+use crate::good;
+
+/// Human readable names of GOOD stat keys.
+const STAT_NAMES: [(&str, &str); 21] = [
+    ("hp", "HP"),
+    ("hp_", "HP%"),
+    ("atk", "ATK"),
+    ("atk_", "ATK%"),
+    ("def", "DEF"),
+    ("def_", "DEF%"),
+    ("eleMas", "Elemental Mastery"),
+    ("enerRech_", "Energy Recharge"),
+    ("heal_", "Healing Bonus"),
+    ("critRate_", "CRIT Rate"),
+    ("critDMG_", "CRIT DMG"),
+    ("physical_dmg_", "Physical DMG Bonus"),
+    ("anemo_dmg_", "Anemo DMG Bonus"),
+    ("geo_dmg_", "Geo DMG Bonus"),
+    ("electro_dmg_", "Electro DMG Bonus"),
+    ("hydro_dmg_", "Hydro DMG Bonus"),
+    ("pyro_dmg_", "Pyro DMG Bonus"),
+    ("cryo_dmg_", "Cryo DMG Bonus"),
+    ("dendro_dmg_", "Dendro DMG Bonus"),
+    ("shield_", "Shield Strength"),
+    ("cd_", "CD Reduction"),
+];
+
+/// Human readable names of GOOD artifact slot keys.
+const SLOT_NAMES: [(&str, &str); 5] = [
+    ("flower", "Flower of Life"),
+    ("plume", "Plume of Death"),
+    ("sands", "Sands of Eon"),
+    ("goblet", "Goblet of Eonothem"),
+    ("circlet", "Circlet of Logos"),
+];
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Cell {
+    Text(String),
+    Number(f64),
+}
+
+impl Cell {
+    fn text(value: impl Into<String>) -> Self {
+        Cell::Text(value.into())
+    }
+
+    fn number(value: impl Into<f64>) -> Self {
+        Cell::Number(value.into())
+    }
+
+    fn yes_no(value: bool) -> Self {
+        Cell::text(if value { "Yes" } else { "No" })
+    }
+}
+
+/// A single table of the export, written as its own CSV file or as one
+/// worksheet of a workbook.
+#[derive(Clone, Debug)]
+pub struct Sheet {
+    pub name: &'static str,
+    pub headers: Vec<&'static str>,
+    pub rows: Vec<Vec<Cell>>,
+}
+
+/// Lay out a GOOD export as spreadsheet tables.
+///
+/// `names` maps the GOOD keys of characters, weapons, artifact sets and
+/// materials back to their names in the game.  Keys without a name are
+/// written as is.
+pub fn sheets(good: &good::Good, names: &HashMap<String, String>) -> Vec<Sheet> {
+    let name = |key: &str| -> String {
+        if let Some(name) = names.get(key) {
+            return name.clone();
+        }
+        match key.strip_prefix(good::TRAVELER_KEY) {
+            Some(element) if !element.is_empty() => format!("Traveler ({element})"),
+            _ => key.to_string(),
+        }
+    };
+    let stat = |key: &str| lookup(&STAT_NAMES, key);
+    let stat_value = |substat: &good::Substat| {
+        if substat.key.ends_with('_') {
+            format!("{} {}%", stat(&substat.key), substat.value)
+        } else {
+            format!("{} {}", stat(&substat.key), substat.value)
+        }
+    };
+
+    let mut sheets = Vec::new();
+
+    if !good.characters.is_empty() {
+        sheets.push(Sheet {
+            name: "Characters",
+            headers: vec![
+                "Name",
+                "Level",
+                "Ascension",
+                "Constellation",
+                "Normal Attack",
+                "Elemental Skill",
+                "Elemental Burst",
+            ],
+            rows: good
+                .characters
+                .iter()
+                .map(|character| {
+                    vec![
+                        Cell::text(name(&character.key)),
+                        Cell::number(character.level),
+                        Cell::number(character.ascension),
+                        Cell::number(character.constellation),
+                        Cell::number(character.talent.auto),
+                        Cell::number(character.talent.skill),
+                        Cell::number(character.talent.burst),
+                    ]
+                })
+                .collect(),
+        });
+    }
+
+    if !good.weapons.is_empty() {
+        sheets.push(Sheet {
+            name: "Weapons",
+            headers: vec![
+                "Name",
+                "Level",
+                "Ascension",
+                "Refinement",
+                "Equipped By",
+                "Locked",
+            ],
+            rows: good
+                .weapons
+                .iter()
+                .map(|weapon| {
+                    vec![
+                        Cell::text(name(&weapon.key)),
+                        Cell::number(weapon.level),
+                        Cell::number(weapon.ascension),
+                        Cell::number(weapon.refinement),
+                        Cell::text(name(&weapon.location)),
+                        Cell::yes_no(weapon.lock),
+                    ]
+                })
+                .collect(),
+        });
+    }
+
+    if !good.artifacts.is_empty() {
+        sheets.push(Sheet {
+            name: "Artifacts",
+            headers: vec![
+                "Set",
+                "Slot",
+                "Rarity",
+                "Level",
+                "Main Stat",
+                "Substat 1",
+                "Substat 2",
+                "Substat 3",
+                "Substat 4",
+                "Unactivated Substat",
+                "Equipped By",
+                "Locked",
+            ],
+            rows: good
+                .artifacts
+                .iter()
+                .map(|artifact| {
+                    let mut row = vec![
+                        Cell::text(name(&artifact.set_key)),
+                        Cell::text(lookup(&SLOT_NAMES, &artifact.slot_key)),
+                        Cell::number(artifact.rarity),
+                        Cell::number(artifact.level),
+                        Cell::text(stat(&artifact.main_stat_key)),
+                    ];
+                    let mut substats = artifact.substats.iter().map(stat_value);
+                    row.extend((0..4).map(|_| Cell::text(substats.next().unwrap_or_default())));
+                    row.push(Cell::text(
+                        artifact
+                            .unactivated_substats
+                            .first()
+                            .map(stat_value)
+                            .unwrap_or_default(),
+                    ));
+                    row.push(Cell::text(name(&artifact.location)));
+                    row.push(Cell::yes_no(artifact.lock));
+                    row
+                })
+                .collect(),
+        });
+    }
+
+    if !good.materials.is_empty() {
+        let mut materials: Vec<(String, u32)> = good
+            .materials
+            .iter()
+            .map(|(key, count)| (name(key), *count))
+            .collect();
+        materials.sort_unstable();
+        sheets.push(Sheet {
+            name: "Materials",
+            headers: vec!["Name", "Count"],
+            rows: materials
+                .into_iter()
+                .map(|(name, count)| vec![Cell::Text(name), Cell::number(count)])
+                .collect(),
+        });
+    }
+
+    sheets
+}
+
+fn lookup(names: &[(&str, &'static str)], key: &str) -> String {
+    names
+        .iter()
+        .find(|(k, _)| *k == key)
+        .map_or_else(|| key.to_string(), |(_, name)| name.to_string())
+}
+
+pub fn to_csv(sheet: &Sheet) -> Result<String> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(&sheet.headers)?;
+    for row in &sheet.rows {
+        writer.write_record(row.iter().map(|cell| match cell {
+            Cell::Text(text) => text.clone(),
+            Cell::Number(number) => number.to_string(),
+        }))?;
+    }
+    Ok(String::from_utf8(writer.into_inner()?)?)
+}
+
+/// Write each sheet to its own CSV file next to `path`, suffixing the file
+/// name with the sheet's name.  Returns the paths written.
+pub fn write_csv(sheets: &[Sheet], path: &Path) -> Result<Vec<PathBuf>> {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    sheets
+        .iter()
+        .map(|sheet| {
+            let path = path.with_file_name(format!("{stem}_{}.csv", sheet.name.to_lowercase()));
+            std::fs::write(&path, to_csv(sheet)?)?;
+            Ok(path)
+        })
+        .collect()
+}
+
+/// Write the sheets as the worksheets of a single XLSX workbook.
+pub fn write_xlsx(sheets: &[Sheet], path: &Path) -> Result<()> {
+    let mut workbook = Workbook::new();
+    let header_format = Format::new().set_bold();
+    for sheet in sheets {
+        let worksheet = workbook.add_worksheet();
+        worksheet.set_name(sheet.name)?;
+        for (col, header) in sheet.headers.iter().enumerate() {
+            worksheet.write_string_with_format(0, col as u16, *header, &header_format)?;
+        }
+        for (row, cells) in sheet.rows.iter().enumerate() {
+            let row = row as u32 + 1;
+            for (col, cell) in cells.iter().enumerate() {
+                match cell {
+                    Cell::Text(text) => worksheet.write_string(row, col as u16, text)?,
+                    Cell::Number(number) => worksheet.write_number(row, col as u16, *number)?,
+                };
+            }
+        }
+        worksheet.set_freeze_panes(1, 0)?;
+        worksheet.autofit();
+    }
+    workbook.save(path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn good() -> good::Good {
+        good::Good {
+            format: "GOOD".to_string(),
+            version: 3,
+            source: "Irminsul".to_string(),
+            characters: vec![good::Character {
+                key: "TravelerAnemo".to_string(),
+                level: 90,
+                constellation: 6,
+                ascension: 6,
+                talent: good::TalentLevel {
+                    auto: 1,
+                    skill: 9,
+                    burst: 10,
+                },
+            }],
+            artifacts: vec![good::Artifact {
+                set_key: "GladiatorsFinale".to_string(),
+                slot_key: "sands".to_string(),
+                level: 20,
+                rarity: 5,
+                main_stat_key: "atk_".to_string(),
+                location: "HuTao".to_string(),
+                lock: true,
+                substats: vec![good::Substat {
+                    key: "critRate_".to_string(),
+                    value: 3.9,
+                    initial_value: 3.9,
+                }],
+                total_rolls: 1,
+                astral_mark: false,
+                elixer_crafted: false,
+                unactivated_substats: Vec::new(),
+            }],
+            weapons: Vec::new(),
+            materials: HashMap::from([("HerosWit".to_string(), 12)]),
+        }
+    }
+
+    #[test]
+    fn keys_are_written_as_game_names() {
+        let names = HashMap::from([
+            (
+                "GladiatorsFinale".to_string(),
+                "Gladiator's Finale".to_string(),
+            ),
+            ("HuTao".to_string(), "Hu Tao".to_string()),
+            ("HerosWit".to_string(), "Hero's Wit".to_string()),
+        ]);
+        let sheets = sheets(&good(), &names);
+
+        let names: Vec<&str> = sheets.iter().map(|sheet| sheet.name).collect();
+        assert_eq!(names, ["Characters", "Artifacts", "Materials"]);
+        assert_eq!(sheets[0].rows[0][0], Cell::text("Traveler (Anemo)"));
+        assert_eq!(
+            sheets[1].rows[0][..6],
+            [
+                Cell::text("Gladiator's Finale"),
+                Cell::text("Sands of Eon"),
+                Cell::number(5),
+                Cell::number(20),
+                Cell::text("ATK%"),
+                Cell::text("CRIT Rate 3.9%"),
+            ]
+        );
+        assert_eq!(sheets[1].rows[0][10], Cell::text("Hu Tao"));
+        assert_eq!(
+            sheets[2].rows[0],
+            [Cell::text("Hero's Wit"), Cell::number(12)]
+        );
+    }
+
+    #[test]
+    fn csv_quotes_names_with_commas() {
+        let sheet = Sheet {
+            name: "Materials",
+            headers: vec!["Name", "Count"],
+            rows: vec![vec![Cell::text("Wit, Hero's"), Cell::number(3)]],
+        };
+
+        assert_eq!(to_csv(&sheet).unwrap(), "Name,Count\n\"Wit, Hero's\",3\n");
+    }
+}
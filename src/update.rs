@@ -1,51 +1,224 @@
-use std::io::Write;
-use std::thread;
+use std::fmt::Display;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use std::{env, process};
 
 use anyhow::{Context, Result, anyhow};
+use async_trait::async_trait;
 use futures_util::StreamExt;
+use minisign_verify::{PublicKey, Signature};
 use reqwest::header;
 use self_update::update::{Release, ReleaseAsset};
-use serde::Deserialize;
+use semver::Version;
+use serde::{Deserialize, Serialize};
 use tokio::sync::{mpsc, watch};
 
-use crate::{AppState, Message, State};
-
-pub fn check_for_new_version() -> Result<Option<Release>> {
-    // This needs to be outside of an async context otherwise it panics.
-    let releases = thread::spawn(move || -> Result<Vec<Release>> {
-        let releases = self_update::backends::github::ReleaseList::configure()
-            .repo_owner("konkers")
-            .repo_name("irminsul")
-            .build()?
-            .fetch()?;
-        Ok(releases)
-    })
-    .join();
-    let releases = releases
-        .map_err(|_| anyhow!("error joining update thread"))?
-        .context("error fetching releases")?;
-
-    // Assume the first release is the latest.
-    let release = releases
-        .first()
-        .ok_or(anyhow!("No releases found"))?
-        .clone();
-
-    if release.version == self_update::cargo_crate_version!() {
+use crate::{AppState, Message, State, UpdateProgress};
+
+const RELEASES_URL: &str = "https://api.github.com/repos/konkers/irminsul/releases";
+const USER_AGENT: &str = "rust-reqwest/self-update";
+
+/// Public half of the key release assets are signed with by
+/// .github/workflows/release.yaml, copied from keys/update.pub by build.rs.
+/// Empty when the build has no key.
+const UPDATE_PUBLIC_KEY: &str = include_str!(concat!(env!("OUT_DIR"), "/update.pub"));
+
+/// Suffix of the minisign signature published alongside each release asset.
+const SIGNATURE_SUFFIX: &str = ".minisig";
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub enum UpdateChannel {
+    #[default]
+    Stable,
+    /// Stable releases and prereleases.
+    Prerelease,
+}
+
+impl Display for UpdateChannel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UpdateChannel::Stable => write!(f, "Stable"),
+            UpdateChannel::Prerelease => write!(f, "Prerelease"),
+        }
+    }
+}
+
+/// Which releases are offered as updates.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct UpdateSettings {
+    #[serde(default)]
+    pub channel: UpdateChannel,
+    /// Release the user chose to skip.  Neither it nor anything older is
+    /// offered again.
+    #[serde(default)]
+    pub skipped_version: Option<String>,
+}
+
+/// Update behaviour asked for on the command line.
+#[derive(Clone, Debug, Default)]
+pub struct UpdateArgs {
+    /// Skip the update check.
+    pub no_update: bool,
+    /// Version that failed to start and was rolled back from.
+    pub rolled_back_from: Option<String>,
+}
+
+/// A release that can be updated to.
+#[derive(Clone, Debug)]
+pub struct AvailableRelease {
+    pub version: Version,
+    pub prerelease: bool,
+    /// Release notes, in markdown.
+    pub notes: Option<String>,
+    pub release: Release,
+}
+
+impl AvailableRelease {
+    fn is_on(&self, channel: UpdateChannel) -> bool {
+        match channel {
+            UpdateChannel::Stable => !self.prerelease && self.version.pre.is_empty(),
+            UpdateChannel::Prerelease => true,
+        }
+    }
+}
+
+/// Where releases are listed.
+#[async_trait]
+pub trait ReleaseSource: Send + Sync {
+    async fn releases(&self) -> Result<Vec<AvailableRelease>>;
+}
+
+/// Releases listed by the GitHub releases API at `url`.
+pub struct GitHubReleases {
+    url: String,
+}
+
+impl GitHubReleases {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self { url: url.into() }
+    }
+}
+
+impl Default for GitHubReleases {
+    fn default() -> Self {
+        Self::new(RELEASES_URL)
+    }
+}
+
+#[derive(Deserialize)]
+struct GitHubRelease {
+    tag_name: String,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    body: Option<String>,
+    #[serde(default)]
+    prerelease: bool,
+    #[serde(default)]
+    draft: bool,
+    #[serde(default)]
+    published_at: Option<String>,
+    assets: Vec<GitHubAsset>,
+}
+
+#[derive(Deserialize)]
+struct GitHubAsset {
+    name: String,
+    /// API url of the asset, which is fetched for its download url.
+    url: String,
+}
+
+#[async_trait]
+impl ReleaseSource for GitHubReleases {
+    async fn releases(&self) -> Result<Vec<AvailableRelease>> {
+        let releases: Vec<GitHubRelease> = reqwest::Client::new()
+            .get(&self.url)
+            .header(header::USER_AGENT, USER_AGENT)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(releases
+            .into_iter()
+            .filter(|release| !release.draft)
+            .filter_map(|release| {
+                let version = release.tag_name.trim_start_matches('v');
+                let version = match Version::parse(version) {
+                    Ok(version) => version,
+                    Err(e) => {
+                        tracing::warn!("Ignoring release {}: {e}", release.tag_name);
+                        return None;
+                    }
+                };
+                Some(AvailableRelease {
+                    prerelease: release.prerelease,
+                    notes: release.body.filter(|body| !body.trim().is_empty()),
+                    release: Release {
+                        name: release.name.unwrap_or_else(|| release.tag_name.clone()),
+                        version: version.to_string(),
+                        date: release.published_at.unwrap_or_default(),
+                        body: None,
+                        assets: release
+                            .assets
+                            .into_iter()
+                            .map(|asset| ReleaseAsset {
+                                download_url: asset.url,
+                                name: asset.name,
+                            })
+                            .collect(),
+                    },
+                    version,
+                })
+            })
+            .collect())
+    }
+}
+
+/// The newest release on the settings' channel that is newer than
+/// `current` and than the skipped version.
+fn pick_update<'a>(
+    releases: &'a [AvailableRelease],
+    current: &Version,
+    settings: &UpdateSettings,
+) -> Option<&'a AvailableRelease> {
+    let skipped = settings
+        .skipped_version
+        .as_deref()
+        .and_then(|version| Version::parse(version).ok());
+    releases
+        .iter()
+        .filter(|release| release.is_on(settings.channel))
+        .filter(|release| release.version > *current)
+        .filter(|release| {
+            skipped
+                .as_ref()
+                .is_none_or(|skipped| release.version > *skipped)
+        })
+        .max_by(|a, b| a.version.cmp(&b.version))
+}
+
+pub async fn check_for_new_version(
+    source: &dyn ReleaseSource,
+    settings: &UpdateSettings,
+) -> Result<Option<AvailableRelease>> {
+    let releases = source.releases().await.context("error fetching releases")?;
+    let current = Version::parse(self_update::cargo_crate_version!())?;
+
+    let Some(release) = pick_update(&releases, &current, settings) else {
         tracing::info!(
-            "{} is current, continuing with app startup",
-            release.version
+            "{current} is current on the {} channel, continuing with app startup",
+            settings.channel
         );
         return Ok(None);
-    }
+    };
 
-    tracing::info!(
-        "Found update {} -> {}",
-        self_update::cargo_crate_version!(),
-        release.version
-    );
+    tracing::info!("Found update {current} -> {}", release.version);
 
-    Ok(Some(release))
+    Ok(Some(release.clone()))
 }
 
 /// Find the asset matching the platform we're running on.
@@ -61,10 +234,195 @@ fn asset_for_target(release: &Release) -> Result<ReleaseAsset> {
     };
 
     release
-        .asset_for(target, None)
+        .assets
+        .iter()
+        .find(|asset| asset.name.contains(target) && !asset.name.ends_with(SIGNATURE_SUFFIX))
+        .cloned()
         .with_context(|| format!("release {} has no {target} asset", release.version))
 }
 
+/// Find the signature published for `asset`.
+fn signature_for(release: &Release, asset: &ReleaseAsset) -> Result<ReleaseAsset> {
+    let name = format!("{}{SIGNATURE_SUFFIX}", asset.name);
+    release
+        .assets
+        .iter()
+        .find(|signature| signature.name == name)
+        .cloned()
+        .with_context(|| {
+            format!(
+                "release {} has no signature for {}",
+                release.version, asset.name
+            )
+        })
+}
+
+/// The trusted comment `asset` of `release` is signed with.  Checking it
+/// keeps a validly signed binary from being passed off as another release
+/// or another platform's asset.
+fn signed_comment(release: &Release, asset: &ReleaseAsset) -> String {
+    format!("irminsul {} {}", release.version, asset.name)
+}
+
+/// Reject a download that wasn't signed by the release key before it
+/// overwrites the running executable, which may well be running as admin.
+fn verify_signature(
+    public_key: &str,
+    path: &Path,
+    signature: &str,
+    expected_comment: &str,
+) -> Result<()> {
+    if public_key.trim().is_empty() {
+        return Err(anyhow!(
+            "this build has no update public key, so updates can't be verified"
+        ));
+    }
+    let public_key = PublicKey::decode(public_key).context("invalid update public key")?;
+    let signature = Signature::decode(signature).context("invalid update signature")?;
+    let data = fs::read(path)?;
+
+    public_key
+        .verify(&data, &signature, false)
+        .map_err(|e| anyhow!("the downloaded update's signature does not verify: {e}"))?;
+    if signature.trusted_comment() != expected_comment {
+        return Err(anyhow!(
+            "the downloaded update is signed as \"{}\" rather than \"{expected_comment}\"",
+            signature.trusted_comment()
+        ));
+    }
+
+    Ok(())
+}
+
+/// Url `asset` is downloaded from.
+async fn download_url(client: &reqwest::Client, asset: &ReleaseAsset) -> Result<String> {
+    #[derive(Deserialize)]
+    struct DownloadMetadata {
+        browser_download_url: String,
+    }
+
+    tracing::info!("fetching artifact info {}", asset.download_url);
+    let metadata: DownloadMetadata = client
+        .get(&asset.download_url)
+        .header(header::USER_AGENT, USER_AGENT)
+        .send()
+        .await
+        .context("Failed to artifact")?
+        .json()
+        .await?;
+
+    Ok(metadata.browser_download_url)
+}
+
+/// Start of the range and total size in a `Content-Range` header value such
+/// as `bytes 100-199/200`.  The size is `None` when the server doesn't know
+/// it.
+fn parse_content_range(value: &str) -> Option<(u64, Option<u64>)> {
+    let (range, total) = value.strip_prefix("bytes ")?.split_once('/')?;
+    let (start, _end) = range.split_once('-')?;
+    let total = match total {
+        "*" => None,
+        total => Some(total.parse().ok()?),
+    };
+    Some((start.parse().ok()?, total))
+}
+
+/// Make one attempt at downloading the rest of `url` into `file`, picking up
+/// from `progress.downloaded`.
+async fn download_attempt(
+    client: &reqwest::Client,
+    url: &str,
+    file: &mut File,
+    progress: &mut UpdateProgress,
+    on_progress: &mut impl FnMut(&UpdateProgress),
+) -> Result<()> {
+    let mut request = client.get(url).header(header::USER_AGENT, USER_AGENT);
+    if progress.downloaded > 0 {
+        request = request.header(header::RANGE, format!("bytes={}-", progress.downloaded));
+    }
+    let response = request.send().await?.error_for_status()?;
+
+    if response.status() == reqwest::StatusCode::PARTIAL_CONTENT {
+        let content_range = response
+            .headers()
+            .get(header::CONTENT_RANGE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_content_range);
+        match content_range {
+            Some((start, total)) if start == progress.downloaded => {
+                progress.total = progress.total.or(total);
+            }
+            _ => {
+                // Start over rather than stitch together the wrong bytes.
+                progress.downloaded = 0;
+                file.set_len(0)?;
+                file.rewind()?;
+                return Err(anyhow!("the download resumed from the wrong place"));
+            }
+        }
+    } else {
+        // The server ignored the range and is sending the whole file.
+        progress.downloaded = 0;
+        progress.total = response.content_length();
+        file.set_len(0)?;
+        file.rewind()?;
+    }
+
+    let mut stream = response.bytes_stream();
+    let mut last_report = Instant::now();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk?;
+        file.write_all(&chunk)?;
+        progress.downloaded += chunk.len() as u64;
+        progress.error = None;
+        if last_report.elapsed() >= PROGRESS_INTERVAL {
+            on_progress(progress);
+            last_report = Instant::now();
+        }
+    }
+
+    if let Some(total) = progress.total
+        && progress.downloaded < total
+    {
+        return Err(anyhow!(
+            "the download ended after {} of {total} bytes",
+            progress.downloaded
+        ));
+    }
+    on_progress(progress);
+    Ok(())
+}
+
+/// Download `url` into `file`, resuming with range requests when the
+/// download fails or stalls.
+async fn download_resumable(
+    client: &reqwest::Client,
+    url: &str,
+    file: &mut File,
+    mut on_progress: impl FnMut(&UpdateProgress),
+) -> Result<()> {
+    let mut progress = UpdateProgress {
+        attempt: 1,
+        ..Default::default()
+    };
+    loop {
+        match download_attempt(client, url, file, &mut progress, &mut on_progress).await {
+            Ok(()) => return Ok(()),
+            Err(e) if progress.attempt < DOWNLOAD_ATTEMPTS => {
+                tracing::warn!(
+                    "Update download failed after {} bytes, retrying: {e:#}",
+                    progress.downloaded
+                );
+                progress.attempt += 1;
+                progress.error = Some(format!("{e:#}"));
+                on_progress(&progress);
+                tokio::time::sleep(DOWNLOAD_RETRY_DELAY).await;
+            }
+            Err(e) => return Err(e.context("could not download the update")),
+        }
+    }
+}
+
 const PE_MAGIC: &[u8] = b"MZ";
 const ELF_MAGIC: &[u8] = b"\x7fELF";
 const MACHO_MAGIC: &[u8] = b"\xcf\xfa\xed\xfe";
@@ -72,9 +430,7 @@ const MACHO_MAGIC: &[u8] = b"\xcf\xfa\xed\xfe";
 /// Reject a download that is not an executable for this platform before it
 /// overwrites the running one.  Installing the wrong platform's binary leaves
 /// an app that cannot run, and so cannot update itself back out of it.
-fn check_is_native_executable(path: &::std::path::Path) -> Result<()> {
-    use ::std::io::Read;
-
+fn check_is_native_executable(path: &Path) -> Result<()> {
     let magic = if cfg!(windows) {
         PE_MAGIC
     } else if cfg!(target_os = "linux") {
@@ -86,7 +442,7 @@ fn check_is_native_executable(path: &::std::path::Path) -> Result<()> {
     };
 
     let mut header = [0u8; 4];
-    let read = ::std::fs::File::open(path)?.read(&mut header)?;
+    let read = File::open(path)?.read(&mut header)?;
 
     if !header[..read].starts_with(magic) {
         return Err(anyhow!(
@@ -97,9 +453,142 @@ fn check_is_native_executable(path: &::std::path::Path) -> Result<()> {
     Ok(())
 }
 
+/// Connecting, or waiting for more of a download, for longer than this fails
+/// the download attempt.
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(30);
+/// Attempts at downloading an update before giving up.
+const DOWNLOAD_ATTEMPTS: u32 = 5;
+const DOWNLOAD_RETRY_DELAY: Duration = Duration::from_secs(2);
+/// How often download progress is reported to the UI.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Suffix of the copy of the previous executable kept until an update has
+/// started successfully.
+const BACKUP_SUFFIX: &str = ".previous";
+/// Suffix of the marker recording an update that hasn't started successfully
+/// yet.
+const PENDING_UPDATE_SUFFIX: &str = ".update.json";
+
+/// An installed update on probation until it shows its window.
+#[derive(Debug, Deserialize, Eq, PartialEq, Serialize)]
+struct PendingUpdate {
+    version: String,
+    previous_version: String,
+    /// Whether the update has been started.  Finding it set on startup means
+    /// the last start died before showing its window.
+    launched: bool,
+}
+
+/// `exe`'s path with `suffix` added to its file name.
+fn path_beside(exe: &Path, suffix: &str) -> PathBuf {
+    let mut name = exe.file_name().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    exe.with_file_name(name)
+}
+
+fn write_pending_update(exe: &Path, pending: &PendingUpdate) -> Result<()> {
+    fs::write(
+        path_beside(exe, PENDING_UPDATE_SUFFIX),
+        serde_json::to_vec(pending)?,
+    )?;
+    Ok(())
+}
+
+/// Record a start of `exe`, returning the pending update if it already
+/// failed to start once.
+fn record_launch(exe: &Path) -> Result<Option<PendingUpdate>> {
+    let marker = path_beside(exe, PENDING_UPDATE_SUFFIX);
+    let mut pending: PendingUpdate = match fs::read(&marker) {
+        Ok(data) => serde_json::from_slice(&data)?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    if pending.launched {
+        return Ok(Some(pending));
+    }
+
+    pending.launched = true;
+    write_pending_update(exe, &pending)?;
+    Ok(None)
+}
+
+/// Forget the pending update of `exe` and the backup kept for it.
+fn clear_pending_update(exe: &Path) -> Result<()> {
+    for suffix in [PENDING_UPDATE_SUFFIX, BACKUP_SUFFIX] {
+        match fs::remove_file(path_beside(exe, suffix)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+/// Mark a freshly installed update as having started successfully, so it is
+/// kept.  Called once the UI draws its first frame, so quitting at a prompt
+/// before the main screen doesn't count as a failed start.
+pub fn mark_update_healthy() {
+    let Ok(current_exe) = env::current_exe() else {
+        return;
+    };
+    if !path_beside(&current_exe, PENDING_UPDATE_SUFFIX).exists() {
+        return;
+    }
+
+    match clear_pending_update(&current_exe) {
+        Ok(()) => tracing::info!("Update started successfully, removed the previous version"),
+        Err(e) => tracing::warn!("Unable to remove the previous version: {e}"),
+    }
+}
+
+/// Restore the previous executable if the update installed over it failed
+/// to start last time, and restart into it with `--rolled-back-from` so it
+/// can tell the user.  Returns if there is nothing to roll back.
+pub fn roll_back_failed_update() {
+    let Ok(current_exe) = env::current_exe() else {
+        return;
+    };
+    let pending = match record_launch(&current_exe) {
+        Ok(Some(pending)) => pending,
+        Ok(None) => return,
+        Err(e) => {
+            tracing::warn!("Unable to check for a pending update: {e}");
+            return;
+        }
+    };
+
+    tracing::warn!(
+        "Update to {} failed to start, restoring {}",
+        pending.version,
+        pending.previous_version
+    );
+    let backup = path_beside(&current_exe, BACKUP_SUFFIX);
+    let restored = self_update::self_replace::self_replace(&backup);
+    if let Err(e) = clear_pending_update(&current_exe) {
+        tracing::warn!("Unable to remove the pending update: {e}");
+    }
+    if let Err(e) = restored {
+        tracing::error!("Unable to restore {}: {e}", pending.previous_version);
+        return;
+    }
+
+    if let Err(e) = process::Command::new(&current_exe)
+        .args(env::args_os().skip(1))
+        .arg("--rolled-back-from")
+        .arg(&pending.version)
+        .spawn()
+    {
+        tracing::error!("Unable to start {}: {e}", pending.previous_version);
+        process::exit(1);
+    }
+    process::exit(0);
+}
+
 /// Replace the running executable, returning whether packet capture
 /// permissions need to be re-granted afterwards.
-async fn download_new_version_and_replace_current(release: Release) -> Result<bool> {
+async fn download_new_version_and_replace_current(
+    release: Release,
+    on_progress: impl FnMut(&UpdateProgress),
+) -> Result<bool> {
     // File capabilities are an attribute of the inode, so replacing the
     // executable drops them.  Running as root loses nothing, since that
     // privilege comes from the invocation rather than from the file.
@@ -114,7 +603,7 @@ async fn download_new_version_and_replace_current(release: Release) -> Result<bo
     // Stage the download next to the executable being replaced.  self_replace
     // finishes with a rename, which cannot cross filesystems, and the current
     // directory is neither guaranteed to be writable nor on the same mount.
-    let current_exe = ::std::env::current_exe().context("could not find the current exe")?;
+    let current_exe = env::current_exe().context("could not find the current exe")?;
     let exe_dir = current_exe
         .parent()
         .context("current exe has no parent directory")?;
@@ -122,74 +611,99 @@ async fn download_new_version_and_replace_current(release: Release) -> Result<bo
         .prefix("self_update")
         .tempdir_in(exe_dir)?;
     let tmp_exe_path = tmp_dir.path().join(&asset.name);
-    let mut tmp_exe = ::std::fs::File::create(&tmp_exe_path)?;
-
-    let client = reqwest::Client::builder().gzip(true).build()?;
+    let mut tmp_exe = File::create(&tmp_exe_path)?;
 
-    #[derive(Deserialize)]
-    struct DownloadMetadata {
-        browser_download_url: String,
-    }
+    // Gzip is turned off, as resuming needs ranges of the file itself rather
+    // than of its compressed form.  The read timeout catches downloads that
+    // stall without the connection dropping.
+    let client = reqwest::Client::builder()
+        .no_gzip()
+        .connect_timeout(DOWNLOAD_TIMEOUT)
+        .read_timeout(DOWNLOAD_TIMEOUT)
+        .build()?;
 
-    tracing::info!("fetching artifact info {}", asset.download_url);
-    let metadata: DownloadMetadata = client
-        .get(&asset.download_url)
-        .header(header::USER_AGENT, "rust-reqwest/self-update")
+    let signature_asset = signature_for(&release, &asset)?;
+    let signature_url = download_url(&client, &signature_asset).await?;
+    let signature = client
+        .get(signature_url)
+        .header(header::USER_AGENT, USER_AGENT)
         .send()
         .await
         .context("Failed to artifact")?
-        .json()
+        .error_for_status()?
+        .text()
         .await?;
 
-    tracing::info!(
-        "downloading {} to {tmp_exe_path:?}",
-        metadata.browser_download_url
-    );
-    let mut stream = client
-        .get(metadata.browser_download_url)
-        .header(header::USER_AGENT, "rust-reqwest/self-update")
-        .send()
-        .await
-        .context("Failed to artifact")?
-        .bytes_stream();
-
-    while let Some(chunk) = stream.next().await {
-        let chunk = chunk?;
-        tmp_exe.write_all(&chunk)?;
-    }
+    let url = download_url(&client, &asset).await?;
+    tracing::info!("downloading {url} to {tmp_exe_path:?}");
+    download_resumable(&client, &url, &mut tmp_exe, on_progress).await?;
     drop(tmp_exe);
 
+    verify_signature(
+        UPDATE_PUBLIC_KEY,
+        &tmp_exe_path,
+        &signature,
+        &signed_comment(&release, &asset),
+    )?;
     check_is_native_executable(&tmp_exe_path)?;
 
     // Release assets are written without the executable bit.
     #[cfg(unix)]
     {
-        use ::std::os::unix::fs::PermissionsExt;
-        ::std::fs::set_permissions(&tmp_exe_path, ::std::fs::Permissions::from_mode(0o755))
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&tmp_exe_path, fs::Permissions::from_mode(0o755))
             .context("could not make the downloaded binary executable")?;
     }
 
+    // Keep the current executable to roll back to should the update fail to
+    // start.
+    fs::copy(&current_exe, path_beside(&current_exe, BACKUP_SUFFIX))
+        .context("could not back up the current exe")?;
+
     tracing::info!("replacing current exe");
     self_update::self_replace::self_replace(tmp_exe_path)?;
 
+    write_pending_update(
+        &current_exe,
+        &PendingUpdate {
+            version: release.version.clone(),
+            previous_version: self_update::cargo_crate_version!().to_owned(),
+            launched: false,
+        },
+    )?;
+
     Ok(caps_lost)
 }
 
+async fn wait_for_cancel(ui_message_rx: &mut mpsc::UnboundedReceiver<Message>) {
+    while !matches!(
+        ui_message_rx.recv().await,
+        Some(Message::UpdateCanceled) | None
+    ) {}
+}
+
 pub async fn check_for_app_update(
     state_tx: &watch::Sender<AppState>,
     ui_message_rx: &mut mpsc::UnboundedReceiver<Message>,
+    source: &dyn ReleaseSource,
+    settings: &UpdateSettings,
 ) -> Result<()> {
     let mut app_state = state_tx.borrow().clone();
     app_state.state = State::CheckingForUpdate;
     state_tx.send(app_state.clone()).unwrap();
 
-    let Some(release) = check_for_new_version()? else {
+    let Some(AvailableRelease { notes, release, .. }) =
+        check_for_new_version(source, settings).await?
+    else {
         // No new version.
         return Ok(());
     };
 
     // Notify user of update and ask for acknowledgement.
-    app_state.state = State::WaitingForUpdateConfirmation(release.version.clone());
+    app_state.state = State::WaitingForUpdateConfirmation {
+        version: release.version.clone(),
+        notes,
+    };
     state_tx.send(app_state.clone()).unwrap();
 
     // Wait acknowledgment.
@@ -201,10 +715,21 @@ pub async fn check_for_app_update(
         };
     }
 
-    app_state.state = State::Updating;
+    app_state.state = State::Updating(UpdateProgress::default());
     state_tx.send(app_state.clone()).unwrap();
 
-    let needs_caps = download_new_version_and_replace_current(release).await?;
+    let report_progress = |progress: &UpdateProgress| {
+        state_tx.send_modify(|state| state.state = State::Updating(progress.clone()));
+    };
+    // Everything after the download finishes happens without yielding, so a
+    // cancel can't leave the executable half replaced.
+    let needs_caps = tokio::select! {
+        needs_caps = download_new_version_and_replace_current(release, report_progress) => needs_caps?,
+        _ = wait_for_cancel(ui_message_rx) => {
+            tracing::info!("Update canceled");
+            return Ok(());
+        }
+    };
 
     app_state.state = State::Updated { needs_caps };
     state_tx.send(app_state.clone()).unwrap();
@@ -217,6 +742,9 @@ pub async fn check_for_app_update(
 
 #[cfg(test)]
 mod tests {
+    use std::net::TcpListener;
+    use std::thread;
+
     use super::*;
 
     fn release(assets: &[&str]) -> Release {
@@ -241,6 +769,11 @@ mod tests {
         "irminsul-windows-x64.exe",
         "irminsul.exe",
         "irminsul-linux-x64",
+        "irminsul-legacy-x64.exe.minisig",
+        "irminsul-macos-arm64.minisig",
+        "irminsul-windows-x64.exe.minisig",
+        "irminsul.exe.minisig",
+        "irminsul-linux-x64.minisig",
     ];
 
     #[test]
@@ -303,6 +836,352 @@ mod tests {
         assert_ne!(asset.name, "irminsul-legacy-x64.exe");
     }
 
+    /// Signatures sort after their assets, but must not be picked even when
+    /// they come first.
+    #[test]
+    fn signatures_are_not_picked_as_the_binary() {
+        let mut assets = PUBLISHED_ASSETS.to_vec();
+        assets.reverse();
+        let release = release(&assets);
+        let asset = asset_for_target(&release).expect("an asset for this platform");
+
+        assert!(!asset.name.ends_with(SIGNATURE_SUFFIX));
+        assert_eq!(
+            signature_for(&release, &asset).unwrap().name,
+            format!("{}.minisig", asset.name)
+        );
+    }
+
+    #[test]
+    fn an_unsigned_asset_has_no_signature() {
+        let release = release(&["irminsul-linux-x64", "irminsul-windows-x64.exe.minisig"]);
+        let asset = release.assets[0].clone();
+
+        signature_for(&release, &asset).expect_err("irminsul-linux-x64 is not signed");
+    }
+
+    /// A key made for these tests, whose secret half has been discarded.
+    const TEST_PUBLIC_KEY: &str = "untrusted comment: minisign public key 9540A88AAB4C0754
+RWRUB0yriqhAlQFlm9t/SHlSEiNxK1dyYADo2C2oAgKo0HJkJKeHXjX/
+";
+
+    const SIGNED_DATA: &[u8] = b"\x7fELF irminsul";
+
+    /// `SIGNED_DATA` signed with the test key.
+    const TEST_SIGNATURE: &str = "untrusted comment: signature from minisign secret key
+RURUB0yriqhAlQL15WGpfRZIPUIEGgPTHAcPhPNzQBu+mZ5XzXgo3SdZ3WUeuDyedgOSfgKi+gfclSv2+7dGniG/IulFUxb1WAE=
+trusted comment: irminsul 0.2.1 irminsul-linux-x64
+k03KKErvxHZZBxK+1rz25Y5sTDPWNzGVOTI9VU/g2wx6wjBz3Sw7PFc3zjdgmQeqejAydihFvFFP2IM2Vm+zCQ==
+";
+
+    const SIGNED_COMMENT: &str = "irminsul 0.2.1 irminsul-linux-x64";
+
+    fn write_download(data: &[u8]) -> (tempfile::TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("irminsul-linux-x64");
+        fs::write(&path, data).unwrap();
+        (dir, path)
+    }
+
+    #[test]
+    fn a_signed_download_is_accepted() {
+        let (_dir, path) = write_download(SIGNED_DATA);
+
+        verify_signature(TEST_PUBLIC_KEY, &path, TEST_SIGNATURE, SIGNED_COMMENT).unwrap();
+    }
+
+    #[test]
+    fn a_tampered_download_is_rejected() {
+        let (_dir, path) = write_download(b"\x7fELF irminsu1");
+
+        verify_signature(TEST_PUBLIC_KEY, &path, TEST_SIGNATURE, SIGNED_COMMENT)
+            .expect_err("the download does not match its signature");
+    }
+
+    #[test]
+    fn a_build_without_a_public_key_rejects_updates() {
+        let (_dir, path) = write_download(SIGNED_DATA);
+
+        verify_signature("", &path, TEST_SIGNATURE, SIGNED_COMMENT)
+            .expect_err("there is no key to verify the signature with");
+    }
+
+    #[test]
+    fn a_signature_for_another_asset_is_rejected() {
+        let (_dir, path) = write_download(SIGNED_DATA);
+
+        verify_signature(
+            TEST_PUBLIC_KEY,
+            &path,
+            TEST_SIGNATURE,
+            "irminsul 0.2.1 irminsul-macos-arm64",
+        )
+        .expect_err("the signature was made for the linux asset");
+    }
+
+    #[test]
+    fn signed_comments_name_the_release_and_asset() {
+        let release = release(&["irminsul-linux-x64"]);
+
+        assert_eq!(
+            signed_comment(&release, &release.assets[0]),
+            "irminsul 0.1.20 irminsul-linux-x64"
+        );
+    }
+
+    #[test]
+    fn an_update_that_fails_to_start_is_rolled_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let exe = dir.path().join("irminsul");
+        let pending = PendingUpdate {
+            version: "0.2.1".to_string(),
+            previous_version: "0.2.0".to_string(),
+            launched: false,
+        };
+        write_pending_update(&exe, &pending).unwrap();
+
+        assert_eq!(record_launch(&exe).unwrap(), None);
+        assert_eq!(
+            record_launch(&exe).unwrap(),
+            Some(PendingUpdate {
+                launched: true,
+                ..pending
+            })
+        );
+    }
+
+    #[test]
+    fn a_healthy_update_is_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let exe = dir.path().join("irminsul");
+        fs::write(path_beside(&exe, BACKUP_SUFFIX), "previous").unwrap();
+        write_pending_update(
+            &exe,
+            &PendingUpdate {
+                version: "0.2.1".to_string(),
+                previous_version: "0.2.0".to_string(),
+                launched: false,
+            },
+        )
+        .unwrap();
+
+        assert_eq!(record_launch(&exe).unwrap(), None);
+        clear_pending_update(&exe).unwrap();
+
+        assert!(!path_beside(&exe, BACKUP_SUFFIX).exists());
+        assert_eq!(record_launch(&exe).unwrap(), None);
+    }
+
+    #[test]
+    fn rollback_files_sit_beside_the_exe() {
+        assert_eq!(
+            path_beside(Path::new("/opt/irminsul.exe"), BACKUP_SUFFIX),
+            Path::new("/opt/irminsul.exe.previous")
+        );
+    }
+
+    fn available(version: &str, prerelease: bool) -> AvailableRelease {
+        AvailableRelease {
+            version: Version::parse(version).unwrap(),
+            prerelease,
+            notes: None,
+            release: release(&[]),
+        }
+    }
+
+    fn picked(releases: &[AvailableRelease], settings: &UpdateSettings) -> Option<String> {
+        let current = Version::parse("0.2.0").unwrap();
+        pick_update(releases, &current, settings).map(|release| release.version.to_string())
+    }
+
+    #[test]
+    fn only_newer_releases_are_offered() {
+        let settings = UpdateSettings::default();
+
+        assert_eq!(
+            picked(
+                &[available("0.1.9", false), available("0.2.0", false)],
+                &settings
+            ),
+            None
+        );
+        assert_eq!(
+            picked(
+                &[available("0.2.1", false), available("0.10.0", false)],
+                &settings
+            ),
+            Some("0.10.0".to_string())
+        );
+    }
+
+    #[test]
+    fn prereleases_are_only_offered_on_the_prerelease_channel() {
+        let releases = [
+            available("0.2.1", false),
+            available("0.3.0-beta.1", false),
+            available("0.3.0", true),
+        ];
+
+        assert_eq!(
+            picked(&releases, &UpdateSettings::default()),
+            Some("0.2.1".to_string())
+        );
+        let settings = UpdateSettings {
+            channel: UpdateChannel::Prerelease,
+            ..Default::default()
+        };
+        assert_eq!(picked(&releases, &settings), Some("0.3.0".to_string()));
+    }
+
+    #[test]
+    fn skipped_versions_are_not_offered() {
+        let settings = UpdateSettings {
+            skipped_version: Some("0.2.2".to_string()),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            picked(
+                &[available("0.2.1", false), available("0.2.2", false)],
+                &settings
+            ),
+            None
+        );
+        assert_eq!(
+            picked(&[available("0.2.3", false)], &settings),
+            Some("0.2.3".to_string())
+        );
+    }
+
+    /// Serve `body` as the response to a single request, returning the url
+    /// to request.
+    fn serve_once(body: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 4096];
+            let _ = stream.read(&mut request);
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\
+                 Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            )
+            .unwrap();
+        });
+        format!("http://{addr}/releases")
+    }
+
+    /// Answer a request with each of `responses` in turn, returning the url
+    /// to request and the requests received.
+    fn serve_responses(
+        responses: &'static [&'static str],
+    ) -> (String, std::sync::mpsc::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (request_tx, request_rx) = std::sync::mpsc::channel();
+        thread::spawn(move || {
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = [0; 4096];
+                let len = stream.read(&mut request).unwrap_or(0);
+                let _ = request_tx.send(String::from_utf8_lossy(&request[..len]).into_owned());
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+        (format!("http://{addr}/irminsul-linux-x64"), request_rx)
+    }
+
+    #[test]
+    fn content_ranges_are_parsed() {
+        assert_eq!(
+            parse_content_range("bytes 100-199/200"),
+            Some((100, Some(200)))
+        );
+        assert_eq!(parse_content_range("bytes 100-199/*"), Some((100, None)));
+        assert_eq!(parse_content_range("bytes */200"), None);
+        assert_eq!(parse_content_range("items 0-1/2"), None);
+    }
+
+    #[tokio::test]
+    async fn interrupted_downloads_are_resumed() {
+        let (url, requests) = serve_responses(&[
+            "HTTP/1.1 200 OK\r\nContent-Length: 10\r\nConnection: close\r\n\r\n01234",
+            "HTTP/1.1 206 Partial Content\r\nContent-Length: 5\r\n\
+             Content-Range: bytes 5-9/10\r\nConnection: close\r\n\r\n56789",
+        ]);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("irminsul-linux-x64");
+        let mut file = File::create(&path).unwrap();
+        let mut last_progress = UpdateProgress::default();
+
+        download_resumable(&reqwest::Client::new(), &url, &mut file, |progress| {
+            last_progress = progress.clone()
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"0123456789");
+        assert!(!requests.recv().unwrap().to_lowercase().contains("range:"));
+        assert!(
+            requests
+                .recv()
+                .unwrap()
+                .to_lowercase()
+                .contains("range: bytes=5-")
+        );
+        assert_eq!(last_progress.downloaded, 10);
+        assert_eq!(last_progress.total, Some(10));
+        assert_eq!(last_progress.attempt, 2);
+    }
+
+    #[tokio::test]
+    async fn downloads_start_over_when_the_range_is_ignored() {
+        let (url, _requests) = serve_responses(&[
+            "HTTP/1.1 200 OK\r\nContent-Length: 10\r\nConnection: close\r\n\r\n01234",
+            "HTTP/1.1 200 OK\r\nContent-Length: 10\r\nConnection: close\r\n\r\n0123456789",
+        ]);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("irminsul-linux-x64");
+        let mut file = File::create(&path).unwrap();
+
+        download_resumable(&reqwest::Client::new(), &url, &mut file, |_| ())
+            .await
+            .unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"0123456789");
+    }
+
+    #[tokio::test]
+    async fn github_releases_are_listed() {
+        let url = serve_once(
+            r#"[
+                {
+                    "tag_name": "v0.3.0-beta.1",
+                    "name": "Beta",
+                    "body": "Fixes",
+                    "prerelease": true,
+                    "draft": false,
+                    "assets": [{"name": "irminsul-linux-x64", "url": "https://example.invalid/1"}]
+                },
+                {"tag_name": "v0.4.0", "draft": true, "assets": []},
+                {"tag_name": "nightly", "assets": []},
+                {"tag_name": "v0.2.1", "body": " ", "assets": []}
+            ]"#,
+        );
+
+        let releases = GitHubReleases::new(url).releases().await.unwrap();
+
+        assert_eq!(releases.len(), 2);
+        assert_eq!(releases[0].version, Version::parse("0.3.0-beta.1").unwrap());
+        assert!(releases[0].prerelease);
+        assert_eq!(releases[0].notes.as_deref(), Some("Fixes"));
+        assert_eq!(releases[0].release.assets[0].name, "irminsul-linux-x64");
+        assert_eq!(releases[1].version, Version::parse("0.2.1").unwrap());
+        assert_eq!(releases[1].notes, None);
+    }
+
     #[test]
     fn a_foreign_binary_is_rejected() {
         let dir = tempfile::tempdir().unwrap();
@@ -315,15 +1194,15 @@ mod tests {
         let native_magic = MACHO_MAGIC;
 
         let native = dir.path().join("native");
-        ::std::fs::write(&native, native_magic).unwrap();
+        fs::write(&native, native_magic).unwrap();
         check_is_native_executable(&native).expect("this platform's magic must be accepted");
 
         let foreign = dir.path().join("foreign");
-        ::std::fs::write(&foreign, b"\x00not an executable").unwrap();
+        fs::write(&foreign, b"\x00not an executable").unwrap();
         check_is_native_executable(&foreign).expect_err("a foreign binary must be rejected");
 
         let empty = dir.path().join("empty");
-        ::std::fs::write(&empty, b"").unwrap();
+        fs::write(&empty, b"").unwrap();
         check_is_native_executable(&empty).expect_err("a truncated download must be rejected");
     }
 }